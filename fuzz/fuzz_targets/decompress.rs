@@ -0,0 +1,8 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+// any input must end in Ok or Err, a panic is a bug
+fuzz_target!(|data: &[u8]| {
+    let _ = ripgzip::decompress(data, std::io::sink());
+});
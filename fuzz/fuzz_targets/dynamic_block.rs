@@ -0,0 +1,21 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+// gzip header without optional fields
+const HEADER: [u8; 10] = [0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 255];
+
+// the data is used as a final dynamic block, so every input exercises
+// the parsing of code lengths
+fuzz_target!(|data: &[u8]| {
+    let Some((&first, rest)) = data.split_first() else {
+        return;
+    };
+
+    let mut input = HEADER.to_vec();
+    // BFINAL = 1, BTYPE = 10
+    input.push(first << 3 | 0b101);
+    input.extend(rest);
+
+    let _ = ripgzip::decompress(input.as_slice(), std::io::sink());
+});
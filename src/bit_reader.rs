@@ -1,7 +1,9 @@
 #![forbid(unsafe_code)]
 
-use byteorder::ReadBytesExt;
-use std::io::{self, BufRead};
+use std::{
+    collections::VecDeque,
+    io::{self, BufRead, Seek, SeekFrom},
+};
 
 ////////////////////////////////////////////////////////////////////////////////
 
@@ -24,19 +26,89 @@ impl BitSequence {
         self.len
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
     pub fn concat(self, other: Self) -> Self {
         Self {
             bits: (self.bits << other.len) | other.bits,
             len: self.len + other.len,
         }
     }
+
+    /// Iterate over the bits from the lowest one, which is the order `read_bits` consumes
+    /// them from the stream. Huffman codes are built with `concat` and are read starting
+    /// from the highest bit, so use `.rev()` for them.
+    pub fn into_bits(self) -> Bits {
+        Bits(self)
+    }
+}
+
+impl IntoIterator for BitSequence {
+    type Item = bool;
+    type IntoIter = Bits;
+
+    fn into_iter(self) -> Bits {
+        self.into_bits()
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Bits(BitSequence);
+
+impl Iterator for Bits {
+    type Item = bool;
+
+    fn next(&mut self) -> Option<bool> {
+        if self.0.is_empty() {
+            return None;
+        }
+
+        let bit = self.0.bits & 1 != 0;
+        self.0 = BitSequence::new(self.0.bits >> 1, self.0.len - 1);
+        Some(bit)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.0.len as usize, Some(self.0.len as usize))
+    }
+}
+
+impl DoubleEndedIterator for Bits {
+    fn next_back(&mut self) -> Option<bool> {
+        if self.0.is_empty() {
+            return None;
+        }
+
+        let len = self.0.len - 1;
+        let bit = (self.0.bits >> len) & 1 != 0;
+        self.0 = BitSequence::new(self.0.bits & ((1 << len) - 1), len);
+        Some(bit)
+    }
 }
 
+impl ExactSizeIterator for Bits {}
+
 ////////////////////////////////////////////////////////////////////////////////
 
 pub struct BitReader<T> {
     stream: T,
     unread_bits: BitSequence,
+    position: u64,
+    // copies of the consumed bytes while recording is enabled
+    recorded: Option<Vec<u8>>,
+    // allocated by the first transaction
+    transactions: Option<Box<Transactions>>,
+}
+
+#[derive(Default)]
+struct Transactions {
+    // unread bits at the start of the current transaction and the bytes consumed since then
+    current: Option<BitSequence>,
+    taken: Vec<u8>,
+    // bytes given back by rollback_transaction, read before the stream
+    replay: VecDeque<u8>,
 }
 
 impl<T: BufRead> BitReader<T> {
@@ -44,19 +116,45 @@ impl<T: BufRead> BitReader<T> {
         Self {
             stream,
             unread_bits: BitSequence::new(0, 0),
+            position: 0,
+            recorded: None,
+            transactions: None,
         }
     }
 
-    // allows to read <= 16 bits
+    /// Number of bytes taken from the underlying reader (not counting the ones read
+    /// through `borrow_reader_from_boundary`).
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Number of bits consumed from the underlying reader, i.e. `position` without the bits
+    /// still buffered.
+    pub fn bit_position(&self) -> u64 {
+        self.position * 8 - self.buffered_bits() as u64
+    }
+
+    // allows to read <= 16 bits; on error no bits are lost, so the read
+    // can be retried (e.g. after io::ErrorKind::WouldBlock).
+    // The missing bytes are taken from the stream's buffer at once rather than
+    // one read call per byte, but never more bytes than the bits requested need
     pub fn read_bits(&mut self, len: u8) -> io::Result<BitSequence> {
         let mut bits: u32 = self.unread_bits.bits() as u32;
         let mut cnt = self.unread_bits.len();
 
         while len > cnt {
-            let byte: u32 = self.stream.read_u8()?.into();
+            let needed = ((len - cnt) as usize).div_ceil(8);
+            let taken = self.take_bytes(needed, |bytes| {
+                for &byte in bytes {
+                    bits |= (byte as u32) << cnt;
+                    cnt += 8;
+                }
+            });
 
-            bits |= byte << cnt;
-            cnt += 8;
+            if let Err(error) = taken {
+                self.unread_bits = BitSequence::new(bits as u16, cnt);
+                return Err(error);
+            }
         }
 
         self.unread_bits = BitSequence::new((bits >> len) as u16, cnt - len);
@@ -67,13 +165,205 @@ impl<T: BufRead> BitReader<T> {
     /// Discard all the unread bits in the current byte and return a mutable reference
     /// to the underlying reader.
     pub fn borrow_reader_from_boundary(&mut self) -> &mut T {
+        self.align();
+        &mut self.stream
+    }
+
+    /// Number of bits left from the last byte taken from the stream (0-7), the ones
+    /// discarded by aligning to a byte boundary.
+    pub fn buffered_bits(&self) -> u8 {
+        self.unread_bits.len()
+    }
+
+    /// Whether the next bit starts a byte, e.g. right after `read_aligned_bytes`.
+    pub fn is_byte_aligned(&self) -> bool {
+        self.buffered_bits() == 0
+    }
+
+    // only the rest of the current byte may be buffered, otherwise discarding it would
+    // skip whole bytes of the stream
+    fn align(&mut self) {
+        debug_assert!(
+            self.unread_bits.len() < 8,
+            "{} unread bits are more than a partial byte",
+            self.unread_bits.len()
+        );
         self.unread_bits = BitSequence::new(0, 0);
+    }
+
+    /// Discard the unread bits in the current byte and read exactly `buf.len()` bytes.
+    /// On error `position()` still accounts for the bytes read before it.
+    pub fn read_aligned_bytes(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        self.align();
+
+        let mut filled = 0;
+        while filled < buf.len() {
+            let rest = &mut buf[filled..];
+            filled += self.take_bytes(rest.len(), |bytes| {
+                rest[..bytes.len()].copy_from_slice(bytes);
+            })?;
+        }
+
+        Ok(())
+    }
+
+    // passes at most `max` next bytes to `take` and consumes them, the ones given back by
+    // rollback_transaction come first; the end of stream is an error
+    fn take_bytes(&mut self, max: usize, take: impl FnOnce(&[u8])) -> io::Result<usize> {
+        let (replay, transaction_taken) = match self.transactions.as_deref_mut() {
+            Some(Transactions {
+                current,
+                taken,
+                replay,
+            }) => (
+                Some(replay).filter(|replay| !replay.is_empty()),
+                current.is_some().then_some(taken),
+            ),
+            None => (None, None),
+        };
+        let available = if let Some(replay) = &replay {
+            replay.as_slices().0
+        } else {
+            loop {
+                match self.stream.fill_buf() {
+                    Ok([]) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                    Err(error) if error.kind() == io::ErrorKind::Interrupted => {}
+                    result => break result?,
+                }
+            }
+        };
+
+        let bytes = &available[..max.min(available.len())];
+        take(bytes);
+        if let Some(recorded) = &mut self.recorded {
+            recorded.extend_from_slice(bytes);
+        }
+        if let Some(transaction_taken) = transaction_taken {
+            transaction_taken.extend_from_slice(bytes);
+        }
+
+        let taken = bytes.len();
+        match replay {
+            Some(replay) => drop(replay.drain(..taken)),
+            None => self.stream.consume(taken),
+        }
+        self.position += taken as u64;
+        Ok(taken)
+    }
+
+    /// Starts keeping the bytes consumed from now on, so that `rollback_transaction` can return
+    /// here even if the stream can't seek, e.g. to retry a read failed with `WouldBlock`.
+    pub fn begin_transaction(&mut self) {
+        let transactions = self.transactions.get_or_insert_with(Default::default);
+        transactions.current = Some(self.unread_bits);
+        transactions.taken.clear();
+    }
+
+    /// Forgets the bytes kept since `begin_transaction`.
+    pub fn commit_transaction(&mut self) {
+        if let Some(transactions) = &mut self.transactions {
+            transactions.current = None;
+            transactions.taken.clear();
+        }
+    }
+
+    /// Returns to the state of `begin_transaction`: the bytes consumed since then are read
+    /// again before the rest of the stream.
+    pub fn rollback_transaction(&mut self) {
+        let Some(transactions) = &mut self.transactions else {
+            return;
+        };
+        let Some(unread_bits) = transactions.current.take() else {
+            return;
+        };
+
+        let taken = transactions.taken.len();
+        for &byte in transactions.taken.iter().rev() {
+            transactions.replay.push_front(byte);
+        }
+        transactions.taken.clear();
+
+        if let Some(recorded) = &mut self.recorded {
+            recorded.truncate(recorded.len().saturating_sub(taken));
+        }
+        self.position -= taken as u64;
+        self.unread_bits = unread_bits;
+    }
+
+    pub fn get_ref(&self) -> &T {
+        &self.stream
+    }
+
+    /// The stream may be extended (e.g. `GrowableInput::push`), but reading from it directly
+    /// desyncs the reader.
+    pub fn get_mut(&mut self) -> &mut T {
         &mut self.stream
     }
 
     pub fn into_inner(self) -> T {
         self.stream
     }
+
+    /// Stream and the unread bits of the current byte, e.g. padding after the final block.
+    pub fn into_parts(self) -> (T, BitSequence) {
+        (self.stream, self.unread_bits)
+    }
+
+    /// While recording, copies of the bytes taken from the stream are kept until
+    /// `take_recorded`, e.g. to pass on the compressed data as it is.
+    pub fn set_recording(&mut self, is_recording: bool) {
+        self.recorded = is_recording.then(Vec::new);
+    }
+
+    /// Bytes taken from the stream since recording was enabled or the previous call.
+    pub fn take_recorded(&mut self) -> Vec<u8> {
+        self.recorded
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+
+    /// Start reading `stream` from scratch, as a new reader would, and return the previous one.
+    /// Unread bits and recorded bytes are dropped and `position()` counts from zero again.
+    pub fn reset(&mut self, stream: T) -> T {
+        self.unread_bits = BitSequence::new(0, 0);
+        self.position = 0;
+        if let Some(recorded) = &mut self.recorded {
+            recorded.clear();
+        }
+        self.transactions = None;
+        std::mem::replace(&mut self.stream, stream)
+    }
+}
+
+/// Saved state of a `BitReader`: the position of its stream and the bits of a partially
+/// read byte.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BitCheckpoint {
+    stream_position: u64,
+    unread_bits: BitSequence,
+    position: u64,
+}
+
+/// Rewinding works only on seekable sources, e.g. files or in-memory cursors, for speculative
+/// parsing like format auto-detection.
+impl<T: BufRead + Seek> BitReader<T> {
+    pub fn checkpoint(&mut self) -> io::Result<BitCheckpoint> {
+        Ok(BitCheckpoint {
+            stream_position: self.stream.stream_position()?,
+            unread_bits: self.unread_bits,
+            position: self.position,
+        })
+    }
+
+    /// Returns to the state of `checkpoint`, which has to be taken from this reader.
+    pub fn restore(&mut self, checkpoint: BitCheckpoint) -> io::Result<()> {
+        self.stream
+            .seek(SeekFrom::Start(checkpoint.stream_position))?;
+        self.unread_bits = checkpoint.unread_bits;
+        self.position = checkpoint.position;
+        Ok(())
+    }
 }
 
 ////////////////////////////////////////////////////////////////////////////////
@@ -81,8 +371,27 @@ impl<T: BufRead> BitReader<T> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::growable_input::GrowableInput;
     use byteorder::ReadBytesExt;
 
+    #[test]
+    fn into_bits() {
+        let seq = BitSequence::new(0b0011, 4);
+        assert_eq!(
+            seq.into_bits().collect::<Vec<_>>(),
+            [true, true, false, false]
+        );
+        assert_eq!(
+            seq.into_bits().rev().collect::<Vec<_>>(),
+            [false, false, true, true]
+        );
+        assert_eq!(seq.into_bits().len(), 4);
+
+        let full = BitSequence::new(u16::MAX, 16);
+        assert!(full.into_iter().rev().all(|bit| bit));
+        assert_eq!(BitSequence::new(0, 0).into_bits().next(), None);
+    }
+
     #[test]
     fn read_bits() -> io::Result<()> {
         let data: &[u8] = &[0b01100011, 0b11011011, 0b10101111];
@@ -100,6 +409,141 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn read_bits_retry() -> io::Result<()> {
+        let mut input = GrowableInput::new();
+        input.push(&[0b01100011]);
+        let mut reader = BitReader::new(input);
+
+        assert_eq!(reader.read_bits(3)?, BitSequence::new(0b011, 3));
+        assert_eq!(
+            reader.read_bits(12).unwrap_err().kind(),
+            io::ErrorKind::WouldBlock
+        );
+
+        reader.stream.push(&[0b11011011]);
+        assert_eq!(reader.read_bits(12)?, BitSequence::new(0b101101101100, 12));
+        Ok(())
+    }
+
+    #[test]
+    fn rollback_transaction() -> io::Result<()> {
+        let mut input = GrowableInput::new();
+        input.push(&[0b01100011, 0b11011011]);
+        let mut reader = BitReader::new(input);
+        reader.set_recording(true);
+        assert_eq!(reader.read_bits(3)?, BitSequence::new(0b011, 3));
+
+        reader.begin_transaction();
+        assert_eq!(reader.read_bits(10)?, BitSequence::new(0b1101101100, 10));
+        assert_eq!(
+            reader.read_bits(8).unwrap_err().kind(),
+            io::ErrorKind::WouldBlock
+        );
+        reader.rollback_transaction();
+        assert_eq!(reader.bit_position(), 3);
+        assert_eq!(reader.take_recorded(), [0b01100011]);
+
+        // the given back bytes come before the new ones
+        reader.get_mut().push(&[0b10101111]);
+        reader.begin_transaction();
+        assert_eq!(reader.read_bits(10)?, BitSequence::new(0b1101101100, 10));
+        let mut buf = [0u8; 1];
+        reader.read_aligned_bytes(&mut buf)?;
+        assert_eq!(buf, [0b10101111]);
+        reader.commit_transaction();
+        reader.rollback_transaction();
+
+        assert_eq!(reader.position(), 3);
+        assert_eq!(reader.take_recorded(), [0b11011011, 0b10101111]);
+        Ok(())
+    }
+
+    #[test]
+    fn read_aligned_bytes() -> io::Result<()> {
+        let data: &[u8] = &[0b01100011, 0b11011011, 0b10101111, 0b00000001];
+        let mut reader = BitReader::new(data);
+        assert_eq!(reader.read_bits(3)?, BitSequence::new(0b011, 3));
+
+        let mut buf = [0u8; 2];
+        reader.read_aligned_bytes(&mut buf)?;
+        assert_eq!(buf, [0b11011011, 0b10101111]);
+
+        reader.read_aligned_bytes(&mut buf[..1])?;
+        assert_eq!(buf[0], 0b00000001);
+        assert_eq!(reader.position(), 4);
+        assert_eq!(
+            reader.read_aligned_bytes(&mut buf).unwrap_err().kind(),
+            io::ErrorKind::UnexpectedEof
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn aligned_at_byte_boundary() -> io::Result<()> {
+        let data: &[u8] = &[0xff; 8];
+        for len in 0..=16 {
+            let mut reader = BitReader::new(data);
+            reader.read_bits(len)?;
+            assert_eq!(reader.buffered_bits(), (8 - len % 8) % 8);
+            assert_eq!(reader.is_byte_aligned(), len % 8 == 0);
+
+            reader.read_aligned_bytes(&mut [0u8; 1])?;
+            assert!(reader.is_byte_aligned());
+            assert_eq!(reader.bit_position(), (len as u64).div_ceil(8) * 8 + 8);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn checkpoint() -> io::Result<()> {
+        let data: &[u8] = &[0b01100011, 0b11011011, 0b10101111];
+        let mut reader = BitReader::new(io::Cursor::new(data));
+        assert_eq!(reader.read_bits(3)?, BitSequence::new(0b011, 3));
+
+        let checkpoint = reader.checkpoint()?;
+        assert_eq!(reader.read_bits(10)?, BitSequence::new(0b1101101100, 10));
+        assert_eq!(reader.position(), 2);
+
+        reader.restore(checkpoint)?;
+        assert_eq!(reader.position(), 1);
+        assert_eq!(reader.read_bits(10)?, BitSequence::new(0b1101101100, 10));
+        assert_eq!(reader.read_bits(8)?, BitSequence::new(0b01111110, 8));
+        Ok(())
+    }
+
+    #[test]
+    fn reset() -> io::Result<()> {
+        let first: &[u8] = &[0b01100011, 0b11011011];
+        let mut reader = BitReader::new(first);
+        assert_eq!(reader.read_bits(11)?, BitSequence::new(0b01101100011, 11));
+
+        let second: &[u8] = &[0b10101111];
+        let previous = reader.reset(second);
+        assert!(previous.is_empty());
+        assert!(reader.is_byte_aligned());
+        assert_eq!(reader.position(), 0);
+        assert_eq!(reader.read_bits(4)?, BitSequence::new(0b1111, 4));
+        assert_eq!(reader.bit_position(), 4);
+        Ok(())
+    }
+
+    #[test]
+    fn recording() -> io::Result<()> {
+        let data: &[u8] = &[0b01100011, 0b11011011, 0b10101111, 0xff];
+        let mut reader = BitReader::new(data);
+        reader.read_bits(3)?;
+        assert!(reader.take_recorded().is_empty());
+
+        reader.set_recording(true);
+        reader.read_bits(10)?;
+        assert_eq!(reader.take_recorded(), [0b11011011]);
+
+        reader.read_aligned_bytes(&mut [0u8; 2])?;
+        assert_eq!(reader.take_recorded(), [0b10101111, 0xff]);
+        Ok(())
+    }
+
     #[test]
     fn borrow_reader_from_boundary() -> io::Result<()> {
         let data: &[u8] = &[0b01100011, 0b11011011, 0b10101111];
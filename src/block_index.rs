@@ -0,0 +1,63 @@
+#![forbid(unsafe_code)]
+
+use std::{
+    cell::{Cell, RefCell},
+    io::BufRead,
+    rc::Rc,
+};
+
+use anyhow::Result;
+
+use crate::{
+    decompress_with_options, BlockHeader, CompressionType, DecompressOptions, MemberHeader,
+    NullWriter, Observer,
+};
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Position and kind of a deflate block found by `build_block_index`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BlockOffset {
+    /// Index of the gzip member containing the block, starting from 0.
+    pub member: usize,
+    /// Offset of the first bit of the block header within the whole input.
+    pub bit_offset: u64,
+    pub is_final: bool,
+    pub compression_type: CompressionType,
+}
+
+#[derive(Default)]
+struct IndexBuilder {
+    members: Cell<usize>,
+    blocks: RefCell<Vec<BlockOffset>>,
+}
+
+impl Observer for Rc<IndexBuilder> {
+    fn on_member_start(&self, _header: &MemberHeader) {
+        self.members.set(self.members.get() + 1);
+    }
+
+    fn on_block(&self, header: &BlockHeader) {
+        self.blocks.borrow_mut().push(BlockOffset {
+            member: self.members.get() - 1,
+            bit_offset: header.bit_offset,
+            is_final: header.is_final,
+            compression_type: header.compression_type,
+        });
+    }
+}
+
+/// Records every deflate block of every member of `input`. The blocks still have to be
+/// decoded to find where the next one starts, but the output is discarded and CRC32 isn't
+/// computed.
+pub fn build_block_index<R: BufRead>(input: R) -> Result<Vec<BlockOffset>> {
+    let builder = Rc::new(IndexBuilder::default());
+    let options = DecompressOptions {
+        skip_crc32: true,
+        observer: Some(Box::new(builder.clone())),
+        ..Default::default()
+    };
+    decompress_with_options(input, NullWriter::new(), &options)?;
+
+    Ok(builder.blocks.take())
+}
@@ -0,0 +1,99 @@
+#![forbid(unsafe_code)]
+
+use std::fmt;
+
+use anyhow::Result;
+
+use crate::{
+    bit_reader::BitSequence,
+    huffman_coding::{canonical_codes, DynamicCodeLengths},
+};
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CodeEntry {
+    pub symbol: u16,
+    pub code: BitSequence,
+}
+
+impl fmt::Display for CodeEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {:0width$b} ({})",
+            self.symbol,
+            self.code.bits(),
+            self.code.len(),
+            width = self.code.len() as usize
+        )
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Huffman code tables of a dynamic block, for debugging encoders.
+#[derive(Clone, Debug)]
+pub struct BlockTrees {
+    pub code_lengths: DynamicCodeLengths,
+    pub litlen: Vec<CodeEntry>,
+    pub distance: Vec<CodeEntry>,
+}
+
+impl BlockTrees {
+    pub fn from_code_lengths(code_lengths: &DynamicCodeLengths) -> Result<Self> {
+        Ok(Self {
+            code_lengths: code_lengths.clone(),
+            litlen: code_entries(&code_lengths.litlen)?,
+            distance: code_entries(&code_lengths.distance)?,
+        })
+    }
+}
+
+impl fmt::Display for BlockTrees {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "literal/length codes:")?;
+        for entry in &self.litlen {
+            writeln!(f, "  {entry}")?;
+        }
+
+        writeln!(f, "distance codes:")?;
+        for entry in &self.distance {
+            writeln!(f, "  {entry}")?;
+        }
+
+        Ok(())
+    }
+}
+
+fn code_entries(code_lengths: &[u8]) -> Result<Vec<CodeEntry>> {
+    Ok(canonical_codes(code_lengths)?
+        .into_iter()
+        .zip(0..)
+        .filter_map(|(code, symbol)| code.map(|code| CodeEntry { symbol, code }))
+        .collect())
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display() -> Result<()> {
+        let code_lengths = DynamicCodeLengths {
+            litlen: vec![2, 0, 1, 2],
+            distance: vec![0, 1],
+        };
+        let trees = BlockTrees::from_code_lengths(&code_lengths)?;
+
+        assert_eq!(
+            trees.to_string(),
+            "literal/length codes:\n  0: 10 (2)\n  2: 0 (1)\n  3: 11 (2)\n\
+             distance codes:\n  1: 0 (1)\n"
+        );
+
+        Ok(())
+    }
+}
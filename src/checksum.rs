@@ -0,0 +1,137 @@
+#![forbid(unsafe_code)]
+
+use crc::{Crc, Digest};
+
+////////////////////////////////////////////////////////////////////////////////
+
+const ADLER_MODULO: u32 = 65521;
+/// CRC32 of gzip, also used for the header CRC16 (its lower half).
+pub(crate) static CRC_ALGORITHM: Crc<u32> = Crc::<u32>::new(&crc::CRC_32_ISO_HDLC);
+
+/// Running checksum of decompressed data, computed by `TrackingWriter` to be checked against
+/// the one stored by the container format.
+pub trait Checksum {
+    fn update(&mut self, data: &[u8]);
+
+    /// Checksum of the data passed to `update` since creation or the last `reset`.
+    fn value(&self) -> u32;
+
+    fn reset(&mut self);
+}
+
+/// CRC32 used by gzip (ISO HDLC).
+#[derive(Clone)]
+pub struct Crc32 {
+    digest: Digest<'static, u32>,
+}
+
+impl Crc32 {
+    pub fn new() -> Self {
+        Self {
+            digest: CRC_ALGORITHM.digest(),
+        }
+    }
+}
+
+impl Default for Crc32 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Checksum for Crc32 {
+    fn update(&mut self, data: &[u8]) {
+        self.digest.update(data);
+    }
+
+    fn value(&self) -> u32 {
+        self.digest.clone().finalize()
+    }
+
+    fn reset(&mut self) {
+        self.digest = CRC_ALGORITHM.digest();
+    }
+}
+
+/// Adler-32 used by zlib.
+#[derive(Clone, Debug)]
+pub struct Adler32 {
+    a: u32,
+    b: u32,
+}
+
+impl Adler32 {
+    pub fn new() -> Self {
+        Self { a: 1, b: 0 }
+    }
+}
+
+impl Default for Adler32 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Checksum for Adler32 {
+    fn update(&mut self, data: &[u8]) {
+        // 5552 bytes is the longest run for which b can't overflow before the modulo
+        for chunk in data.chunks(5552) {
+            for &byte in chunk {
+                self.a += byte as u32;
+                self.b += self.a;
+            }
+            self.a %= ADLER_MODULO;
+            self.b %= ADLER_MODULO;
+        }
+    }
+
+    fn value(&self) -> u32 {
+        (self.b << 16) | self.a
+    }
+
+    fn reset(&mut self) {
+        *self = Self::new();
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc32() {
+        let mut crc32 = Crc32::new();
+        crc32.update(b"1234");
+        crc32.update(b"56789");
+        assert_eq!(crc32.value(), 0xcbf43926);
+
+        crc32.reset();
+        assert_eq!(crc32.value(), 0);
+    }
+
+    #[test]
+    fn adler32() {
+        let mut adler = Adler32::new();
+        adler.update(b"Wiki");
+        adler.update(b"pedia");
+        assert_eq!(adler.value(), 0x11e60398);
+
+        adler.reset();
+        assert_eq!(adler.value(), 1);
+
+        let data = [0xff; 100000];
+        adler.update(&data);
+        assert_eq!(adler.value(), adler32_naive(&data));
+    }
+
+    fn adler32_naive(data: &[u8]) -> u32 {
+        let (mut a, mut b) = (1u64, 0u64);
+        for &byte in data {
+            a = (a + byte as u64) % ADLER_MODULO as u64;
+            b = (b + a) % ADLER_MODULO as u64;
+        }
+        ((b << 16) | a) as u32
+    }
+}
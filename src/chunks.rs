@@ -0,0 +1,118 @@
+#![forbid(unsafe_code)]
+
+use std::{
+    io::{self, BufRead, Write},
+    mem,
+    sync::mpsc::{self, Receiver, SyncSender},
+    thread::{self, JoinHandle},
+};
+
+use anyhow::{anyhow, Result};
+
+use crate::decompress;
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Size of the chunks yielded by `decompress_chunks`, except for the last one.
+pub const OUTPUT_CHUNK_SIZE: usize = 1 << 16;
+
+// chunks in flight between the decoding thread and the iterator
+const QUEUE_LEN: usize = 4;
+
+// collects the output into chunks of OUTPUT_CHUNK_SIZE and sends the full ones
+struct ChunkSender {
+    sender: SyncSender<Result<Vec<u8>>>,
+    chunk: Vec<u8>,
+}
+
+impl ChunkSender {
+    fn send_chunk(&mut self) -> io::Result<()> {
+        let chunk = mem::replace(&mut self.chunk, Vec::with_capacity(OUTPUT_CHUNK_SIZE));
+        self.sender
+            .send(Ok(chunk))
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "chunks are no longer read"))
+    }
+
+    // the last chunk is partial, but never empty
+    fn finish(&mut self) -> io::Result<()> {
+        if self.chunk.is_empty() {
+            return Ok(());
+        }
+        self.send_chunk()
+    }
+}
+
+impl Write for ChunkSender {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut rest = buf;
+        while !rest.is_empty() {
+            let len = rest.len().min(OUTPUT_CHUNK_SIZE - self.chunk.len());
+            self.chunk.extend_from_slice(&rest[..len]);
+            rest = &rest[len..];
+
+            if self.chunk.len() == OUTPUT_CHUNK_SIZE {
+                self.send_chunk()?;
+            }
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// yields the chunks sent by the decoding thread, then an error if the thread panicked
+// (the channel is closed then as well, which would otherwise look like the end of output)
+struct ChunkReceiver {
+    receiver: Receiver<Result<Vec<u8>>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Iterator for ChunkReceiver {
+    type Item = Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Ok(chunk) = self.receiver.recv() {
+            return Some(chunk);
+        }
+
+        match self.handle.take()?.join() {
+            Ok(()) => None,
+            Err(_) => Some(Err(anyhow!("decompression thread panicked"))),
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Iterates over the decompressed output in owned chunks of `OUTPUT_CHUNK_SIZE` bytes (the last
+/// one may be shorter), e.g. to send them to other threads. Decompression runs on a separate
+/// thread, a few chunks ahead of the iterator. A decompression error (or a panic of the thread)
+/// is yielded as the last item, after the output decoded before it.
+pub fn decompress_chunks<R: BufRead + Send + 'static>(
+    input: R,
+) -> impl Iterator<Item = Result<Vec<u8>>> {
+    let (sender, receiver) = mpsc::sync_channel(QUEUE_LEN);
+
+    let handle = thread::spawn(move || {
+        let mut writer = ChunkSender {
+            sender,
+            chunk: Vec::with_capacity(OUTPUT_CHUNK_SIZE),
+        };
+        let result = decompress(input, &mut writer);
+        // output decoded before an error is passed on too, as `decompress` would have written it
+        let finished = writer.finish();
+
+        if let Err(error) = result.and(finished.map_err(Into::into)) {
+            // fails only if the iterator is already dropped
+            let _ = writer.sender.send(Err(error));
+        }
+    });
+
+    ChunkReceiver {
+        receiver,
+        handle: Some(handle),
+    }
+}
@@ -0,0 +1,69 @@
+#![forbid(unsafe_code)]
+
+use std::io::{self, BufRead, Read};
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Reader keeping track of how many bytes were consumed from the inner one.
+pub struct CountingReader<T> {
+    inner: T,
+    position: u64,
+}
+
+impl<T> CountingReader<T> {
+    pub fn new(inner: T) -> Self {
+        Self { inner, position: 0 }
+    }
+
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+}
+
+impl<T: Read> Read for CountingReader<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.position += read as u64;
+        Ok(read)
+    }
+}
+
+impl<T: BufRead> BufRead for CountingReader<T> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.inner.consume(amt);
+        self.position += amt as u64;
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn position() -> io::Result<()> {
+        let data: &[u8] = b"first line\nsecond";
+        let mut reader = CountingReader::new(data);
+
+        let mut buf = [0u8; 3];
+        reader.read_exact(&mut buf)?;
+        assert_eq!(reader.position(), 3);
+
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        assert_eq!(reader.position(), 11);
+
+        reader.read_to_string(&mut line)?;
+        assert_eq!(reader.position(), 17);
+        Ok(())
+    }
+}
@@ -0,0 +1,45 @@
+#![forbid(unsafe_code)]
+
+use std::io::{self, Write};
+
+use crate::checksum::{Checksum, Crc32};
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Writer computing CRC32 and size of everything written through it, across members.
+pub struct Crc32Writer<W> {
+    inner: W,
+    crc32: Crc32,
+    byte_count: u64,
+}
+
+impl<W: Write> Crc32Writer<W> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            crc32: Crc32::new(),
+            byte_count: 0,
+        }
+    }
+
+    pub fn crc32(&self) -> u32 {
+        self.crc32.value()
+    }
+
+    pub fn byte_count(&self) -> u64 {
+        self.byte_count
+    }
+}
+
+impl<W: Write> Write for Crc32Writer<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.crc32.update(&buf[..written]);
+        self.byte_count += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
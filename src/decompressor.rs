@@ -0,0 +1,43 @@
+#![forbid(unsafe_code)]
+
+use std::{
+    collections::VecDeque,
+    io::{BufRead, Write},
+};
+
+use anyhow::Result;
+
+use crate::{decompress_buffered, DecompressOptions, DecompressStats};
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Decompressor for many inputs in a row, e.g. thousands of small files. The 32KB history
+/// buffer is allocated once and reused by every call (unless a call fails).
+#[derive(Default)]
+pub struct Decompressor {
+    options: DecompressOptions,
+    history: VecDeque<u8>,
+}
+
+impl Decompressor {
+    pub fn new(options: DecompressOptions) -> Self {
+        Self {
+            options,
+            history: VecDeque::new(),
+        }
+    }
+
+    pub fn options(&self) -> &DecompressOptions {
+        &self.options
+    }
+
+    /// Same as `decompress_with_options` with the options of this decompressor. No state
+    /// other than buffer allocations is kept between calls.
+    pub fn decompress<R: BufRead, W: Write>(
+        &mut self,
+        input: R,
+        output: W,
+    ) -> Result<DecompressStats> {
+        decompress_buffered(input, output, &self.options, &mut self.history, None)
+    }
+}
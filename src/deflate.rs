@@ -1,26 +1,35 @@
 #![forbid(unsafe_code)]
 
-use std::io::{BufRead, Write};
+use std::io::{self, BufRead, Write};
 
-use anyhow::{bail, ensure, Context, Result};
-use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use byteorder::WriteBytesExt;
 
-use crate::huffman_coding::{DistanceToken, HuffmanCoding, LitLenToken};
+use crate::error::DecompressError;
+use crate::huffman_coding::{DistanceToken, DynamicCodeLengths, HuffmanCoding, LitLenToken};
 use crate::tracking_writer::TrackingWriter;
 use crate::{
-    bit_reader::BitReader,
-    huffman_coding::{build_fixed_trees, decode_litlen_distance_trees},
+    bit_reader::{BitReader, BitSequence},
+    huffman_coding::{build_dynamic_trees, fixed_trees, read_dynamic_code_lengths},
 };
 
 ////////////////////////////////////////////////////////////////////////////////
 
-#[derive(Debug)]
+const STORED_CHUNK_SIZE: usize = 8192;
+/// Largest window allowed by the format, the farthest distance codes can express.
+pub const MAX_WINDOW_SIZE: usize = 32768;
+
+#[derive(Clone, Debug)]
 pub struct BlockHeader {
+    /// Index of the block within its member, starting from 0.
+    pub index: usize,
+    /// Offset of the first bit of the block header within the whole input.
+    pub bit_offset: u64,
     pub is_final: bool,
     pub compression_type: CompressionType,
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum CompressionType {
     Uncompressed = 0,
     FixedTree = 1,
@@ -28,20 +37,83 @@ pub enum CompressionType {
     Reserved = 3,
 }
 
+/// LZ77 decision decoded from a block: a byte to output as is, or a copy of `length` bytes
+/// starting `distance` bytes back. Content of stored blocks is reported as literals.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Lz77Token {
+    Literal(u8),
+    Match { length: u16, distance: u16 },
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 
 pub enum NextBlock<R, W> {
     /// indicates start of Footer of outer format (example: GZIP)
-    /// and contains IOs of DeflateReader
-    Footer(R, TrackingWriter<W>),
+    /// and contains IOs of DeflateReader with the padding bits discarded after the final block
+    Footer(R, TrackingWriter<W>, BitSequence),
 
     BlockOrError(Result<DeflateBlock<R, W>>),
 }
 
+/// With resumption enabled, every read that has to be done as a whole (a block header, a token,
+/// a chunk of a stored block) puts the consumed bits back on error, so it can be retried after
+/// `WouldBlock` of an input that isn't filled yet.
+#[derive(Clone, Copy, Default)]
+struct Resumption {
+    is_enabled: bool,
+    is_waiting_for_input: bool,
+}
+
+impl Resumption {
+    fn begin<R: BufRead>(&mut self, bit_reader: &mut BitReader<R>) {
+        self.is_waiting_for_input = false;
+        if self.is_enabled {
+            bit_reader.begin_transaction();
+        }
+    }
+
+    fn end<R: BufRead, T>(
+        &mut self,
+        bit_reader: &mut BitReader<R>,
+        result: Result<T>,
+    ) -> Result<T> {
+        if !self.is_enabled {
+            return result;
+        }
+
+        match &result {
+            Ok(_) => bit_reader.commit_transaction(),
+            Err(error) => {
+                bit_reader.rollback_transaction();
+                self.is_waiting_for_input = error.chain().any(|cause| {
+                    cause
+                        .downcast_ref::<io::Error>()
+                        .is_some_and(|error| error.kind() == io::ErrorKind::WouldBlock)
+                });
+            }
+        }
+        result
+    }
+
+    fn read<R: BufRead, T>(
+        &mut self,
+        bit_reader: &mut BitReader<R>,
+        read: impl FnOnce(&mut BitReader<R>) -> Result<T>,
+    ) -> Result<T> {
+        self.begin(bit_reader);
+        let result = read(bit_reader);
+        self.end(bit_reader, result)
+    }
+}
+
 pub struct DeflateReader<R, W> {
     bit_reader: BitReader<R>,
     writer: TrackingWriter<W>,
     is_exhausted: bool,
+    input_offset: u64,
+    block_index: usize,
+    window_size: usize,
+    resumption: Resumption,
 }
 
 impl<R: BufRead, W: Write> DeflateReader<R, W> {
@@ -50,26 +122,112 @@ impl<R: BufRead, W: Write> DeflateReader<R, W> {
             bit_reader,
             writer,
             is_exhausted: false,
+            input_offset: 0,
+            block_index: 0,
+            window_size: MAX_WINDOW_SIZE,
+            resumption: Resumption::default(),
         }
     }
 
+    /// See `Resumption`, disabled by default.
+    pub(crate) fn set_resumable(&mut self, is_resumable: bool) {
+        self.resumption.is_enabled = is_resumable;
+    }
+
+    /// Whether the last error was `WouldBlock` of the input, after which the failed read
+    /// can be retried. Always `false` unless resumable.
+    pub(crate) fn is_waiting_for_input(&self) -> bool {
+        self.resumption.is_waiting_for_input
+    }
+
+    /// Distances beyond `window_size` fail with `DecompressError::DistanceTooFar`, e.g. for
+    /// a zlib stream declaring a smaller window. `MAX_WINDOW_SIZE` by default.
+    pub fn set_window_size(&mut self, window_size: usize) {
+        self.window_size = window_size;
+    }
+
+    /// Offset of the deflate data within the whole input, used in error messages.
+    pub fn set_input_offset(&mut self, input_offset: u64) {
+        self.input_offset = input_offset;
+    }
+
+    pub fn get_reader(&self) -> &R {
+        self.bit_reader.get_ref()
+    }
+
+    pub fn get_reader_mut(&mut self) -> &mut R {
+        self.bit_reader.get_mut()
+    }
+
+    pub fn get_writer(&self) -> &TrackingWriter<W> {
+        &self.writer
+    }
+
+    pub fn writer_mut(&mut self) -> &mut TrackingWriter<W> {
+        &mut self.writer
+    }
+
+    /// See `BitReader::set_recording`.
+    pub fn set_recording_input(&mut self, is_recording: bool) {
+        self.bit_reader.set_recording(is_recording);
+    }
+
+    /// Compressed bytes consumed since the previous call while recording is enabled.
+    pub fn take_recorded_input(&mut self) -> Vec<u8> {
+        self.bit_reader.take_recorded()
+    }
+
     // reads header and transforms to DeflateBlock
     pub fn next_block(mut self) -> NextBlock<R, W> {
         if self.is_exhausted {
-            return NextBlock::Footer(self.bit_reader.into_inner(), self.writer);
+            let (reader, padding) = self.bit_reader.into_parts();
+            return NextBlock::Footer(reader, self.writer, padding);
         }
 
-        match self.read_header() {
-            Ok(header) => NextBlock::BlockOrError(Ok(DeflateBlock {
-                bit_reader: self.bit_reader,
-                writer: self.writer,
-                header,
-            })),
-            Err(error) => NextBlock::BlockOrError(Err(error)),
+        NextBlock::BlockOrError(
+            self.read_block_start()
+                .map(|(header, code_lengths)| self.into_block(header, code_lengths)),
+        )
+    }
+
+    /// Reads the header of the next block, together with the code lengths of a dynamic one.
+    pub(crate) fn read_block_start(&mut self) -> Result<(BlockHeader, Option<DynamicCodeLengths>)> {
+        self.resumption.begin(&mut self.bit_reader);
+        let result = self.read_header().and_then(|header| {
+            // code lengths of a dynamic block are a part of its header
+            let code_lengths = if header.compression_type == CompressionType::DynamicTree {
+                let code_lengths = read_dynamic_code_lengths(&mut self.bit_reader)
+                    .context("Failed to read code lengths!")?;
+                Some(code_lengths)
+            } else {
+                None
+            };
+            Ok((header, code_lengths))
+        });
+        self.resumption.end(&mut self.bit_reader, result)
+    }
+
+    pub(crate) fn into_block(
+        self,
+        header: BlockHeader,
+        code_lengths: Option<DynamicCodeLengths>,
+    ) -> DeflateBlock<R, W> {
+        DeflateBlock {
+            bit_reader: self.bit_reader,
+            writer: self.writer,
+            header,
+            code_lengths,
+            input_offset: self.input_offset,
+            window_size: self.window_size,
+            resumption: self.resumption,
+            dynamic_trees: None,
+            stored_content: None,
         }
     }
 
-    fn read_header(&mut self) -> Result<BlockHeader> {
+    pub(crate) fn read_header(&mut self) -> Result<BlockHeader> {
+        let bit_offset = self.input_offset * 8 + self.bit_reader.bit_position();
+
         let bfinal = self
             .bit_reader
             .read_bits(1)
@@ -91,17 +249,36 @@ impl<R: BufRead, W: Write> DeflateReader<R, W> {
         };
 
         Ok(BlockHeader {
+            index: self.block_index,
+            bit_offset,
             is_final: (bfinal == 1),
             compression_type,
         })
     }
 }
 
+type Trees = (HuffmanCoding<LitLenToken>, HuffmanCoding<DistanceToken>);
+
+// progress of a stored block, kept for resuming
+#[derive(Clone, Copy)]
+struct StoredContent {
+    len: u16,
+    start: u64,
+    remaining: usize,
+}
+
 // extracts data from block
 pub struct DeflateBlock<R, W> {
     bit_reader: BitReader<R>,
     writer: TrackingWriter<W>,
     header: BlockHeader,
+    code_lengths: Option<DynamicCodeLengths>,
+    input_offset: u64,
+    window_size: usize,
+    resumption: Resumption,
+    // built on the first call of decode
+    dynamic_trees: Option<Box<Trees>>,
+    stored_content: Option<StoredContent>,
 }
 
 impl<R: BufRead, W: Write> DeflateBlock<R, W> {
@@ -109,113 +286,232 @@ impl<R: BufRead, W: Write> DeflateBlock<R, W> {
         &self.header
     }
 
-    // reads block content to writer and transforms DeflateBlock back to DeflateReader
-    pub fn read_content(mut self) -> Result<DeflateReader<R, W>> {
-        if self.header.compression_type == CompressionType::Reserved {
-            bail!("unsupported block type!");
-        } else if self.header.compression_type == CompressionType::Uncompressed {
-            self.process_uncompressed()?;
-        } else {
-            let (litlen_tree, distance_tree) =
-                if self.header.compression_type == CompressionType::FixedTree {
-                    build_fixed_trees()
-                } else {
-                    decode_litlen_distance_trees(&mut self.bit_reader)
-                }
-                .context("Failed to build trees!")?;
+    pub fn get_writer(&self) -> &TrackingWriter<W> {
+        &self.writer
+    }
 
-            self.process_with_trees(litlen_tree, distance_tree)?;
-        }
+    /// Code lengths declared by a dynamic block, `None` for other block types.
+    pub fn get_code_lengths(&self) -> Option<&DynamicCodeLengths> {
+        self.code_lengths.as_ref()
+    }
 
-        Ok(DeflateReader {
-            bit_reader: self.bit_reader,
-            writer: self.writer,
-            is_exhausted: self.header.is_final,
-        })
+    pub(crate) fn get_reader_mut(&mut self) -> &mut R {
+        self.bit_reader.get_mut()
     }
 
-    fn process_uncompressed(&mut self) -> Result<()> {
-        let reader = self.bit_reader.borrow_reader_from_boundary();
-        let len = reader
-            .read_u16::<LittleEndian>()
-            .context("Failed to read LEN!")?;
+    /// See `DeflateReader::is_waiting_for_input`.
+    pub(crate) fn is_waiting_for_input(&self) -> bool {
+        self.resumption.is_waiting_for_input
+    }
 
-        let nlen = reader
-            .read_u16::<LittleEndian>()
-            .context("Failed to read NLEN!")?;
+    // reads block content to writer and transforms DeflateBlock back to DeflateReader,
+    // every decoded token is also passed to on_token, if any
+    pub fn read_content(
+        mut self,
+        on_token: Option<&mut dyn FnMut(Lz77Token)>,
+    ) -> Result<DeflateReader<R, W>> {
+        self.decode(on_token)?;
+        Ok(self.into_reader())
+    }
 
-        ensure!(len == !nlen, "nlen check failed!");
+    /// Reads the block content to the writer. If resumable and waiting for input, calling it
+    /// again continues from the token that failed.
+    pub(crate) fn decode(&mut self, on_token: Option<&mut dyn FnMut(Lz77Token)>) -> Result<()> {
+        match self.header.compression_type {
+            CompressionType::Reserved => bail!("unsupported block type!"),
+            CompressionType::Uncompressed => self.process_uncompressed(on_token),
+            CompressionType::FixedTree | CompressionType::DynamicTree => {
+                let dynamic_trees = match (self.dynamic_trees.take(), &self.code_lengths) {
+                    (Some(trees), _) => Some(trees),
+                    (None, Some(code_lengths)) => Some(Box::new(
+                        build_dynamic_trees(code_lengths).context("Failed to build trees!")?,
+                    )),
+                    (None, None) => None,
+                };
+
+                let (litlen_tree, distance_tree) =
+                    dynamic_trees.as_deref().unwrap_or(fixed_trees());
+                let result = self.process_with_trees(litlen_tree, distance_tree, on_token);
+                self.dynamic_trees = dynamic_trees;
+                result
+            }
+        }
+    }
 
-        let mut buf = vec![0u8; len as usize];
-        reader
-            .read_exact(&mut buf)
-            .context("Failed to read the content of uncompressed block!")?;
+    pub(crate) fn into_reader(self) -> DeflateReader<R, W> {
+        DeflateReader {
+            bit_reader: self.bit_reader,
+            writer: self.writer,
+            is_exhausted: self.header.is_final,
+            input_offset: self.input_offset,
+            block_index: self.header.index + 1,
+            window_size: self.window_size,
+            resumption: self.resumption,
+        }
+    }
 
-        self.writer
-            .write_all(&buf)
-            .context("Failed to write the content of uncompressed block!")?;
+    fn process_uncompressed(
+        &mut self,
+        mut on_token: Option<&mut dyn FnMut(Lz77Token)>,
+    ) -> Result<()> {
+        let mut content = match self.stored_content {
+            Some(content) => content,
+            None => {
+                let len = self.resumption.read(&mut self.bit_reader, |bit_reader| {
+                    let mut len = [0u8; 2];
+                    bit_reader
+                        .read_aligned_bytes(&mut len)
+                        .context("Failed to read LEN!")?;
+                    let len = u16::from_le_bytes(len);
+
+                    let mut nlen = [0u8; 2];
+                    bit_reader
+                        .read_aligned_bytes(&mut nlen)
+                        .context("Failed to read NLEN!")?;
+                    let nlen = u16::from_le_bytes(nlen);
+
+                    ensure!(len == !nlen, "nlen check failed!");
+                    Ok(len)
+                })?;
+
+                StoredContent {
+                    len,
+                    start: self.bit_reader.position(),
+                    remaining: len as usize,
+                }
+            }
+        };
+        self.stored_content = Some(content);
+
+        // copying in chunks keeps memory bounded regardless of the block length
+        let mut buf = [0u8; STORED_CHUNK_SIZE];
+        let input_offset = self.input_offset;
+        while content.remaining > 0 {
+            let chunk = &mut buf[..content.remaining.min(STORED_CHUNK_SIZE)];
+            self.resumption.read(&mut self.bit_reader, |bit_reader| {
+                bit_reader.read_aligned_bytes(chunk).with_context(|| {
+                    let position = bit_reader.position();
+                    format!(
+                        "Uncompressed block truncated: {} of {} bytes read, input ends at byte {}!",
+                        position - content.start,
+                        content.len,
+                        input_offset + position
+                    )
+                })
+            })?;
+
+            self.writer
+                .write_all(chunk)
+                .context("Failed to write the content of uncompressed block!")?;
+
+            if let Some(on_token) = &mut on_token {
+                chunk
+                    .iter()
+                    .for_each(|&byte| on_token(Lz77Token::Literal(byte)));
+            }
+            content.remaining -= chunk.len();
+            self.stored_content = Some(content);
+        }
 
         Ok(())
     }
 
     fn process_with_trees(
         &mut self,
-        litlen_tree: HuffmanCoding<LitLenToken>,
-        distance_tree: HuffmanCoding<DistanceToken>,
+        litlen_tree: &HuffmanCoding<LitLenToken>,
+        distance_tree: &HuffmanCoding<DistanceToken>,
+        mut on_token: Option<&mut dyn FnMut(Lz77Token)>,
     ) -> Result<()> {
+        let window_size = self.window_size;
         loop {
-            let token = litlen_tree
-                .read_symbol(&mut self.bit_reader)
-                .context("literal/length token expected!")?;
+            // a token is read as a whole before any output, so it can be read again on resuming
+            let token = self.resumption.read(&mut self.bit_reader, |bit_reader| {
+                read_token(bit_reader, litlen_tree, distance_tree, window_size)
+            })?;
+            let Some(token) = token else {
+                break;
+            };
 
             match token {
-                LitLenToken::Literal(byte) => {
+                Lz77Token::Literal(byte) => {
                     self.writer
                         .write_u8(byte)
                         .context("Failed to write Literal!")?;
                 }
 
-                LitLenToken::Length { base, extra_bits } => {
-                    self.process_length_token(base, extra_bits, &distance_tree)?;
+                Lz77Token::Match { length, distance } => {
+                    // distances within the window may still reach before the start of output,
+                    // that is checked by write_previous
+                    self.writer
+                        .write_previous(distance as usize, length as usize)
+                        .context("Wrong Length/Distance!")?;
                 }
+            }
 
-                LitLenToken::EndOfBlock => break,
+            if let Some(on_token) = &mut on_token {
+                on_token(token);
             }
         }
 
         Ok(())
     }
+}
 
-    fn process_length_token(
-        &mut self,
-        len_base: u16,
-        len_extra_bits: u8,
-        distance_tree: &HuffmanCoding<DistanceToken>,
-    ) -> Result<()> {
-        let len_offset = self
-            .bit_reader
-            .read_bits(len_extra_bits)
-            .context("Failed to read Length extra bits!")?
-            .bits();
-
-        let len = (len_base + len_offset) as usize;
-
-        let distance_token = distance_tree
-            .read_symbol(&mut self.bit_reader)
-            .context("distance token expected!")?;
-
-        let dist_offset = self
-            .bit_reader
-            .read_bits(distance_token.extra_bits)
-            .context("Failed to read Distance extra bits!")?
-            .bits();
-
-        let dist = (distance_token.base + dist_offset) as usize;
+// `None` for the end of block
+fn read_token<R: BufRead>(
+    bit_reader: &mut BitReader<R>,
+    litlen_tree: &HuffmanCoding<LitLenToken>,
+    distance_tree: &HuffmanCoding<DistanceToken>,
+    window_size: usize,
+) -> Result<Option<Lz77Token>> {
+    let token = litlen_tree
+        .read_symbol(bit_reader)
+        .context("literal/length token expected!")?;
+
+    let (len_base, len_extra_bits) = match token {
+        LitLenToken::Literal(byte) => return Ok(Some(Lz77Token::Literal(byte))),
+        LitLenToken::Length { base, extra_bits } => (base, extra_bits),
+        LitLenToken::EndOfBlock => return Ok(None),
+    };
+
+    // all distance code lengths may be zero in a block of literals only, but then
+    // it can't have lengths either
+    if distance_tree.is_empty() {
+        return Err(anyhow!(DecompressError::EmptyCodeTable))
+            .context("Length code in a block without distance codes!");
+    }
 
-        self.writer
-            .write_previous(dist, len)
-            .context("Wrong Length/Distance!")?;
+    let len_offset = bit_reader
+        .read_bits(len_extra_bits)
+        .context("Failed to read Length extra bits!")?
+        .bits();
+
+    // can't overflow for the valid length codes, but a wrap would silently desync decoding
+    let length = len_base
+        .checked_add(len_offset)
+        .with_context(|| format!("Length {len_base} + {len_offset} overflows!"))?;
+
+    let distance_token = distance_tree
+        .read_symbol(bit_reader)
+        .context("distance token expected!")?;
+
+    let dist_offset = bit_reader
+        .read_bits(distance_token.extra_bits)
+        .context("Failed to read Distance extra bits!")?
+        .bits();
+
+    let base = distance_token.base;
+    let distance = base
+        .checked_add(dist_offset)
+        .with_context(|| format!("Distance {base} + {dist_offset} overflows!"))?;
+
+    ensure!(
+        distance as usize <= window_size,
+        DecompressError::DistanceTooFar {
+            dist: distance,
+            window: window_size,
+        }
+    );
 
-        Ok(())
-    }
+    Ok(Some(Lz77Token::Match { length, distance }))
 }
@@ -0,0 +1,157 @@
+#![forbid(unsafe_code)]
+
+use std::fmt;
+
+use crate::bit_reader::BitSequence;
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Errors callers may want to tell apart; they're returned wrapped in `anyhow::Error`
+/// and can be recovered with `downcast_ref`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecompressError {
+    UnsupportedCompressionMethod(u8),
+    HeaderCrcMismatch {
+        expected: u16,
+        actual: u16,
+    },
+    UnterminatedString {
+        field: &'static str,
+    },
+    StringTooLong {
+        field: &'static str,
+        limit: u64,
+    },
+    InvalidExtraField {
+        declared_len: u16,
+    },
+    InvalidBlockHeader(String),
+    /// `actual` is `None` when the stream needs a preset dictionary but none was given.
+    DictionaryMismatch {
+        expected: u32,
+        actual: Option<u32>,
+    },
+    /// ISIZE of a footer doesn't match the size of the decompressed member (modulo 2^32).
+    LengthMismatch {
+        expected: u32,
+        actual: u64,
+    },
+    /// Externally supplied CRC32 and size don't match the footer of the (only) member.
+    ExpectedFooterMismatch {
+        expected_crc32: u32,
+        expected_size: u64,
+        footer_crc32: u32,
+        footer_size: u32,
+    },
+    /// Externally supplied CRC32 and size don't match the decompressed output.
+    ExpectedOutputMismatch {
+        expected_crc32: u32,
+        expected_size: u64,
+        actual_crc32: u32,
+        actual_size: u64,
+    },
+    /// No code of a Huffman table is a prefix of these bits (the longest code is 15 bits).
+    InvalidSymbol {
+        bits: BitSequence,
+    },
+    /// A symbol is read with a Huffman table without codes, e.g. a distance after a length in
+    /// a block whose distance code lengths are all zero.
+    EmptyCodeTable,
+    TooManyMembers {
+        limit: usize,
+    },
+    /// Data after the last member doesn't start with the gzip magic.
+    TrailingNonGzipData {
+        offset: u64,
+        first_bytes: Vec<u8>,
+    },
+    /// Back-reference farther than the window of the stream, as opposed to one reaching
+    /// before the start of output.
+    DistanceTooFar {
+        dist: u16,
+        window: usize,
+    },
+    Timeout,
+}
+
+impl fmt::Display for DecompressError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnsupportedCompressionMethod(method @ 0..=7) => {
+                write!(f, "reserved compression method: {method}")
+            }
+            Self::UnsupportedCompressionMethod(method) => {
+                write!(f, "unsupported compression method: {method}")
+            }
+            Self::HeaderCrcMismatch { expected, actual } => write!(
+                f,
+                "header crc16 check failed: expected {expected:#06x}, computed {actual:#06x}"
+            ),
+            Self::UnterminatedString { field } => {
+                write!(f, "input ended before the terminating NUL of the {field}")
+            }
+            Self::StringTooLong { field, limit } => {
+                write!(f, "{field} is longer than {limit} bytes")
+            }
+            Self::InvalidExtraField { declared_len } => {
+                write!(
+                    f,
+                    "input ended inside the extra field of declared length {declared_len}"
+                )
+            }
+            Self::InvalidBlockHeader(reason) => write!(f, "invalid block header: {reason}"),
+            Self::DictionaryMismatch { expected, actual } => match actual {
+                Some(actual) => write!(
+                    f,
+                    "preset dictionary mismatch: expected id {expected:#010x}, got {actual:#010x}"
+                ),
+                None => write!(f, "preset dictionary {expected:#010x} is required"),
+            },
+            Self::LengthMismatch { expected, actual } => write!(
+                f,
+                "length check failed: footer says {expected} bytes, decompressed {actual}"
+            ),
+            Self::ExpectedFooterMismatch {
+                expected_crc32,
+                expected_size,
+                footer_crc32,
+                footer_size,
+            } => write!(
+                f,
+                "footer doesn't match the expected values: crc32 {footer_crc32:#010x}, size \
+                 {footer_size} instead of {expected_crc32:#010x}, {expected_size}"
+            ),
+            Self::ExpectedOutputMismatch {
+                expected_crc32,
+                expected_size,
+                actual_crc32,
+                actual_size,
+            } => write!(
+                f,
+                "output doesn't match the expected values: crc32 {actual_crc32:#010x}, size \
+                 {actual_size} instead of {expected_crc32:#010x}, {expected_size}"
+            ),
+            Self::InvalidSymbol { bits } => write!(
+                f,
+                "no Huffman code matches the bits {:0width$b}",
+                bits.bits(),
+                width = bits.len() as usize
+            ),
+            Self::EmptyCodeTable => write!(f, "Huffman code table has no codes"),
+            Self::TooManyMembers { limit } => write!(f, "more than {limit} gzip members"),
+            Self::TrailingNonGzipData {
+                offset,
+                first_bytes,
+            } => write!(
+                f,
+                "non-gzip data at offset {offset} after the last member, starting with {first_bytes:02x?}"
+            ),
+            Self::DistanceTooFar { dist, window } => {
+                write!(f, "distance {dist} exceeds the window of {window} bytes")
+            }
+            Self::Timeout => write!(f, "decompression deadline exceeded"),
+        }
+    }
+}
+
+impl std::error::Error for DecompressError {}
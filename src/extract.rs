@@ -0,0 +1,100 @@
+#![forbid(unsafe_code)]
+
+use std::{
+    fs::File,
+    io::{self, BufRead, BufWriter, Write},
+    path::{Path, PathBuf},
+    time::{Duration, UNIX_EPOCH},
+};
+
+use anyhow::{Context, Result};
+
+use crate::members::MemberReader;
+
+////////////////////////////////////////////////////////////////////////////////
+
+// output of the member being extracted, switched between members
+#[derive(Default)]
+struct MemberFile {
+    file: Option<BufWriter<File>>,
+}
+
+impl Write for MemberFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match &mut self.file {
+            Some(file) => file.write(buf),
+            None => Err(io::Error::other("no member file is open")),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.file {
+            Some(file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+// files are never overwritten: a member whose file already exists (e.g. because of
+// a duplicate name) gets its index appended to the name, failing if that exists as well
+fn create_member_file(path: PathBuf, index: usize) -> Result<(PathBuf, File)> {
+    let create = |path: &Path| File::options().write(true).create_new(true).open(path);
+
+    let path = match create(&path) {
+        Ok(file) => return Ok((path, file)),
+        Err(error) if error.kind() == io::ErrorKind::AlreadyExists => {
+            let mut renamed = path.into_os_string();
+            renamed.push(format!(".{index}"));
+            PathBuf::from(renamed)
+        }
+        Err(error) => {
+            return Err(error).with_context(|| format!("Failed to create {}!", path.display()))
+        }
+    };
+
+    let file = create(&path).with_context(|| format!("Failed to create {}!", path.display()))?;
+    Ok((path, file))
+}
+
+/// Writes every member of `input` to its own file in `dir`: `dir/<safe_name>` for members
+/// with a safe name (see `MemberHeader::safe_name`), `dir/member_<index>` otherwise. Existing
+/// files aren't overwritten, `.<index>` is appended to the name instead (e.g. for members with
+/// the same name), and if that file exists too, extraction fails. The
+/// modification time of a file is set from MTIME, if it's present. Returns the paths of the
+/// files with the number of bytes written to each.
+pub fn extract_all_to_dir<R: BufRead>(input: R, dir: &Path) -> Result<Vec<(PathBuf, u64)>> {
+    let mut members = MemberReader::new(input, MemberFile::default());
+    let mut manifest = Vec::new();
+
+    while let Some(header) = members.next_header()? {
+        let path = match header.safe_name() {
+            Some(name) => dir.join(name),
+            None => dir.join(format!("member_{}", manifest.len())),
+        };
+        let (path, file) = create_member_file(path, manifest.len())?;
+        members.output_mut()?.file = Some(BufWriter::new(file));
+
+        let output_size = members.read_data()?;
+
+        let file = members
+            .output_mut()?
+            .file
+            .take()
+            .context("Member file was lost!")?;
+        let file = file
+            .into_inner()
+            .map_err(|error| error.into_error())
+            .with_context(|| format!("Failed to write {}!", path.display()))?;
+
+        // MTIME = 0 means that the time isn't available
+        if header.modification_time != 0 {
+            let modified = UNIX_EPOCH + Duration::from_secs(header.modification_time.into());
+            file.set_modified(modified)
+                .with_context(|| format!("Failed to set mtime of {}!", path.display()))?;
+        }
+
+        manifest.push((path, output_size));
+    }
+
+    Ok(manifest)
+}
@@ -0,0 +1,96 @@
+#![forbid(unsafe_code)]
+
+use std::{
+    collections::VecDeque,
+    io::{self, BufRead, Read},
+};
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// In-memory input that is filled in chunks as they arrive. Until it's closed, running
+/// out of data is reported as `io::ErrorKind::WouldBlock` rather than end of stream.
+#[derive(Debug, Default)]
+pub struct GrowableInput {
+    buffer: VecDeque<u8>,
+    is_closed: bool,
+}
+
+impl GrowableInput {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, data: &[u8]) {
+        self.buffer.extend(data);
+    }
+
+    /// Mark that no more data will be pushed, so running out of data becomes end of stream.
+    pub fn close(&mut self) {
+        self.is_closed = true;
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.is_closed
+    }
+
+    /// Number of pushed bytes that weren't read yet.
+    pub fn available(&self) -> usize {
+        self.buffer.len()
+    }
+}
+
+impl Read for GrowableInput {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let len = available.len().min(buf.len());
+
+        buf[..len].copy_from_slice(&available[..len]);
+        self.consume(len);
+        Ok(len)
+    }
+}
+
+impl BufRead for GrowableInput {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.buffer.is_empty() && !self.is_closed {
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+
+        Ok(self.buffer.make_contiguous())
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.buffer.drain(..amt);
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read() -> io::Result<()> {
+        let mut input = GrowableInput::new();
+        let mut buf = [0u8; 4];
+        assert_eq!(
+            input.read(&mut buf).unwrap_err().kind(),
+            io::ErrorKind::WouldBlock
+        );
+
+        input.push(&[1, 2, 3]);
+        assert_eq!(input.read(&mut buf[..2])?, 2);
+        assert_eq!(input.available(), 1);
+        assert_eq!(input.read(&mut buf[2..])?, 1);
+        assert_eq!(buf, [1, 2, 3, 0]);
+        assert_eq!(
+            input.read(&mut buf).unwrap_err().kind(),
+            io::ErrorKind::WouldBlock
+        );
+
+        input.close();
+        assert_eq!(input.read(&mut buf)?, 0);
+        Ok(())
+    }
+}
@@ -1,19 +1,30 @@
 #![forbid(unsafe_code)]
 
-use std::io::{BufRead, Write};
+use std::{
+    fmt,
+    io::{self, BufRead, Read, Write},
+};
 
 use anyhow::{bail, ensure, Context, Result};
 use byteorder::{LittleEndian, ReadBytesExt};
-use crc::Crc;
+use crc::Digest;
+use log::warn;
 
-use crate::{bit_reader::BitReader, deflate::DeflateReader, tracking_writer::TrackingWriter};
+use crate::{
+    bit_reader::BitReader, checksum::CRC_ALGORITHM, deflate::DeflateReader, error::DecompressError,
+    tracking_writer::TrackingWriter,
+};
 
 ////////////////////////////////////////////////////////////////////////////////
 
-const ID1: u8 = 0x1f;
-const ID2: u8 = 0x8b;
+pub(crate) const ID1: u8 = 0x1f;
+pub(crate) const ID2: u8 = 0x8b;
 
 const CM_DEFLATE: u8 = 8;
+const OS_UNKNOWN: u8 = 255;
+
+/// Default limit on the length of the name and comment fields, excluding the terminating NUL.
+pub const DEFAULT_MAX_STRING_LEN: u64 = 1 << 16;
 
 const FTEXT_OFFSET: u8 = 0;
 const FHCRC_OFFSET: u8 = 1;
@@ -21,6 +32,10 @@ const FEXTRA_OFFSET: u8 = 2;
 const FNAME_OFFSET: u8 = 3;
 const FCOMMENT_OFFSET: u8 = 4;
 
+fn latin1_bytes(text: &str) -> impl Iterator<Item = u8> + '_ {
+    text.chars().map(|char| u8::try_from(char).unwrap_or(b'?'))
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 
 #[derive(Debug)]
@@ -28,6 +43,8 @@ pub struct MemberHeader {
     pub compression_method: CompressionMethod,
     pub modification_time: u32,
     pub extra: Option<Vec<u8>>,
+    /// Name and comment are ISO-8859-1 text per RFC 1952: every byte is the char of the same
+    /// code, and chars beyond U+00FF are written as `?`.
     pub name: Option<String>,
     pub comment: Option<String>,
     pub extra_flags: u8,
@@ -37,32 +54,151 @@ pub struct MemberHeader {
 }
 
 impl MemberHeader {
+    pub fn builder() -> MemberHeaderBuilder {
+        MemberHeaderBuilder::new()
+    }
+
     pub fn crc16(&self) -> u16 {
-        let crc = Crc::<u32>::new(&crc::CRC_32_ISO_HDLC);
-        let mut digest = crc.digest();
+        (CRC_ALGORITHM.checksum(&self.fields_to_bytes()) & 0xffff) as u16
+    }
 
-        digest.update(&[ID1, ID2, self.compression_method.into(), self.flags().0]);
-        digest.update(&self.modification_time.to_le_bytes());
-        digest.update(&[self.extra_flags, self.os]);
+    /// Clears the fields that vary between otherwise identical archives: MTIME is zeroed, OS is
+    /// set to unknown, XFL to zero and the file name is dropped.
+    pub fn make_reproducible(&mut self) {
+        self.modification_time = 0;
+        self.os = OS_UNKNOWN;
+        self.extra_flags = 0;
+        self.name = None;
+    }
+
+    /// Whether both headers have the same fields, optionally ignoring MTIME (e.g. to check that
+    /// two archives are equivalent modulo timestamps).
+    pub fn metadata_eq(&self, other: &MemberHeader, ignore_mtime: bool) -> bool {
+        self.compression_method == other.compression_method
+            && (ignore_mtime || self.modification_time == other.modification_time)
+            && self.extra == other.extra
+            && self.name == other.name
+            && self.comment == other.comment
+            && self.extra_flags == other.extra_flags
+            && self.os == other.os
+            && self.has_crc == other.has_crc
+            && self.is_text == other.is_text
+    }
+
+    /// Serializes the header in the RFC 1952 layout, with CRC16 recomputed if `has_crc` is set.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.fields_to_bytes();
+        if self.has_crc {
+            bytes.extend(self.crc16().to_le_bytes());
+        }
+        bytes
+    }
+
+    // everything covered by the header CRC16
+    fn fields_to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![ID1, ID2, self.compression_method.into(), self.flags().0];
+        bytes.extend(self.modification_time.to_le_bytes());
+        bytes.extend([self.extra_flags, self.os]);
 
         if let Some(extra) = &self.extra {
-            digest.update(&(extra.len() as u16).to_le_bytes());
-            digest.update(extra);
+            bytes.extend((extra.len() as u16).to_le_bytes());
+            bytes.extend(extra);
         }
 
         if let Some(name) = &self.name {
-            digest.update(name.as_bytes());
-            digest.update(&[0]);
+            bytes.extend(latin1_bytes(name));
+            bytes.push(0);
         }
 
         if let Some(comment) = &self.comment {
-            digest.update(comment.as_bytes());
-            digest.update(&[0]);
+            bytes.extend(latin1_bytes(comment));
+            bytes.push(0);
         }
 
-        (digest.finalize() & 0xffff) as u16
+        bytes
+    }
+
+    /// The file name if it can be safely used as a single path component, i.e. it isn't
+    /// empty, `.` or `..`, and has no separators or drive prefixes. Anything creating files
+    /// from member names must go through this.
+    pub fn safe_name(&self) -> Option<&str> {
+        let name = self.name.as_deref()?;
+
+        let is_safe = !matches!(name, "" | "." | "..") && !name.contains(['/', '\\', ':']);
+
+        is_safe.then_some(name)
     }
 
+    /// Name of the file system the member was produced on, per the OS values of RFC 1952.
+    pub fn os_label(&self) -> &'static str {
+        match self.os {
+            0 => "FAT",
+            1 => "Amiga",
+            2 => "VMS",
+            3 => "Unix",
+            4 => "VM/CMS",
+            5 => "Atari TOS",
+            6 => "HPFS",
+            7 => "Macintosh",
+            8 => "Z-System",
+            9 => "CP/M",
+            10 => "TOPS-20",
+            11 => "NTFS",
+            12 => "QDOS",
+            13 => "Acorn RISCOS",
+            255 => "unknown",
+            _ => "undefined",
+        }
+    }
+
+    /// Meaning of the extra flags (XFL) for deflate: the compression level used.
+    pub fn extra_flags_label(&self) -> &'static str {
+        match self.extra_flags {
+            0 => "none",
+            2 => "maximum compression",
+            4 => "fastest",
+            _ => "undefined",
+        }
+    }
+
+    /// Best-effort guess of the program that produced the member, from the OS byte, XFL,
+    /// MTIME and the extra field. Purely a heuristic: headers are easy to fake, and many tools
+    /// write identical ones, so `None` or a wrong guess are both possible.
+    pub fn likely_producer(&self) -> Option<&'static str> {
+        if self.has_extra_subfield(*b"BC") {
+            return Some("bgzip");
+        }
+        if self.has_extra_subfield(*b"RA") {
+            return Some("dictzip");
+        }
+
+        let is_bare = self.extra.is_none() && self.name.is_none() && self.comment.is_none();
+        match (self.os, self.modification_time) {
+            // Python always stores MTIME, Go leaves it zero by default
+            (255, 0) if is_bare => Some("go"),
+            (255, _) => Some("python"),
+            (0, 0) if is_bare && self.extra_flags == 0 => Some("java"),
+            // zlib's gzip wrapper (used by Apache mod_deflate too) doesn't store MTIME
+            (3, 0) if is_bare => Some("zlib"),
+            (3, _) => Some("gzip"),
+            _ => None,
+        }
+    }
+
+    // subfields of the extra field are SI1, SI2, LEN (2 bytes) and LEN bytes of data
+    fn has_extra_subfield(&self, id: [u8; 2]) -> bool {
+        let mut extra = self.extra.as_deref().unwrap_or_default();
+        while extra.len() >= 4 {
+            if extra[..2] == id {
+                return true;
+            }
+            let len = u16::from_le_bytes([extra[2], extra[3]]) as usize;
+            extra = extra.get(4 + len..).unwrap_or_default();
+        }
+        false
+    }
+
+    /// Flags byte (FLG) describing this header, i.e. which optional fields are present.
     pub fn flags(&self) -> MemberFlags {
         let mut flags = MemberFlags(0);
         flags.set_is_text(self.is_text);
@@ -74,14 +210,119 @@ impl MemberHeader {
     }
 }
 
+/// Builds a `MemberHeader` starting from deflate, unknown OS, zero MTIME and no optional fields.
+#[derive(Debug)]
+pub struct MemberHeaderBuilder {
+    header: MemberHeader,
+}
+
+impl Default for MemberHeaderBuilder {
+    fn default() -> Self {
+        Self {
+            header: MemberHeader {
+                compression_method: CompressionMethod::Deflate,
+                modification_time: 0,
+                extra: None,
+                name: None,
+                comment: None,
+                extra_flags: 0,
+                os: OS_UNKNOWN,
+                has_crc: false,
+                is_text: false,
+            },
+        }
+    }
+}
+
+impl MemberHeaderBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn compression_method(mut self, compression_method: CompressionMethod) -> Self {
+        self.header.compression_method = compression_method;
+        self
+    }
+
+    pub fn modification_time(mut self, modification_time: u32) -> Self {
+        self.header.modification_time = modification_time;
+        self
+    }
+
+    pub fn extra(mut self, extra: Vec<u8>) -> Self {
+        self.header.extra = Some(extra);
+        self
+    }
+
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.header.name = Some(name.into());
+        self
+    }
+
+    pub fn comment(mut self, comment: impl Into<String>) -> Self {
+        self.header.comment = Some(comment.into());
+        self
+    }
+
+    pub fn extra_flags(mut self, extra_flags: u8) -> Self {
+        self.header.extra_flags = extra_flags;
+        self
+    }
+
+    pub fn os(mut self, os: u8) -> Self {
+        self.header.os = os;
+        self
+    }
+
+    /// Whether `to_bytes` appends the header CRC16.
+    pub fn has_crc(mut self, has_crc: bool) -> Self {
+        self.header.has_crc = has_crc;
+        self
+    }
+
+    pub fn is_text(mut self, is_text: bool) -> Self {
+        self.header.is_text = is_text;
+        self
+    }
+
+    pub fn build(self) -> MemberHeader {
+        self.header
+    }
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum CompressionMethod {
     Deflate,
     Unknown(u8),
 }
 
+impl CompressionMethod {
+    /// Short label for listings and logs; `Display` also includes the value of unknown methods.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Deflate => "deflate",
+            Self::Unknown(_) => "unknown",
+        }
+    }
+
+    /// Values 0-7 are reserved by RFC 1952, unlike the other unknown ones which aren't
+    /// mentioned by it at all.
+    pub fn is_reserved(&self) -> bool {
+        matches!(self, Self::Unknown(0..=7))
+    }
+}
+
+impl fmt::Display for CompressionMethod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Deflate => f.write_str(self.as_str()),
+            Self::Unknown(x) => write!(f, "{}({})", self.as_str(), x),
+        }
+    }
+}
+
 impl From<u8> for CompressionMethod {
     fn from(value: u8) -> Self {
         match value {
@@ -102,9 +343,21 @@ impl From<CompressionMethod> for u8 {
 
 ////////////////////////////////////////////////////////////////////////////////
 
-#[derive(Debug)]
+#[derive(Clone, Copy, PartialEq, Eq)]
 pub struct MemberFlags(u8);
 
+impl fmt::Debug for MemberFlags {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MemberFlags")
+            .field("is_text", &self.is_text())
+            .field("has_crc", &self.has_crc())
+            .field("has_extra", &self.has_extra())
+            .field("has_name", &self.has_name())
+            .field("has_comment", &self.has_comment())
+            .finish()
+    }
+}
+
 #[allow(unused)]
 impl MemberFlags {
     fn bit(&self, n: u8) -> bool {
@@ -172,124 +425,229 @@ pub struct MemberFooter {
 
 pub struct GzipReader<R, W> {
     reader: R,
-    underlying_writer: W,
+    writer: TrackingWriter<W>,
+    lenient_header_crc: bool,
+    lenient_extra_field: bool,
+    max_string_len: u64,
 }
 
 impl<R: BufRead, W: Write> GzipReader<R, W> {
-    pub fn new(reader: R, underlying_writer: W) -> Self {
+    pub fn new(reader: R, writer: TrackingWriter<W>) -> Self {
         Self {
             reader,
-            underlying_writer,
+            writer,
+            lenient_header_crc: false,
+            lenient_extra_field: false,
+            max_string_len: DEFAULT_MAX_STRING_LEN,
         }
     }
 
-    // reads Gzip header and transforms to DeflateReader
-    pub fn next_member(mut self) -> Result<(MemberHeader, DeflateReader<R, W>)> {
-        let header = self
-            .read_header()
-            .context("Failure while reading header!")?;
-
-        match header.compression_method {
-            CompressionMethod::Unknown(x) => bail!("unsupported compression method: {x}"),
-            CompressionMethod::Deflate => Ok((
-                header,
-                DeflateReader::new(
-                    BitReader::new(self.reader),
-                    TrackingWriter::new(self.underlying_writer),
-                ),
-            )),
-        }
+    /// In lenient mode a header CRC16 mismatch is only logged as a warning.
+    pub fn set_lenient_header_crc(&mut self, lenient_header_crc: bool) {
+        self.lenient_header_crc = lenient_header_crc;
+    }
+
+    /// In lenient mode an extra field cut short by the end of input is kept truncated
+    /// with a warning.
+    pub fn set_lenient_extra_field(&mut self, lenient_extra_field: bool) {
+        self.lenient_extra_field = lenient_extra_field;
+    }
+
+    /// Name and comment fields longer than `max_string_len` bytes fail with `StringTooLong`.
+    pub fn set_max_string_len(&mut self, max_string_len: u64) {
+        self.max_string_len = max_string_len;
+    }
+
+    pub fn next_header(&mut self) -> Result<MemberHeader> {
+        self.read_header().context("Failure while reading header!")
+    }
+
+    // transforms to DeflateReader once the header of a deflate member is read
+    pub fn into_deflate_reader(self) -> DeflateReader<R, W> {
+        DeflateReader::new(BitReader::new(self.reader), self.writer)
+    }
+
+    pub fn get_reader(&self) -> &R {
+        &self.reader
+    }
+
+    pub fn get_reader_mut(&mut self) -> &mut R {
+        &mut self.reader
+    }
+
+    pub fn writer_mut(&mut self) -> &mut TrackingWriter<W> {
+        &mut self.writer
+    }
+
+    pub fn flush_writer(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    pub fn into_writer(self) -> TrackingWriter<W> {
+        self.writer
     }
 
     pub fn is_empty(&mut self) -> Result<bool> {
         Ok(self.reader.fill_buf()?.is_empty())
     }
 
+    /// Up to `len` next bytes of the input, without consuming them. Fewer bytes are returned
+    /// if the input's buffer holds fewer.
+    pub fn peek(&mut self, len: usize) -> Result<&[u8]> {
+        let buf = self.reader.fill_buf()?;
+        Ok(&buf[..buf.len().min(len)])
+    }
+
+    /// Whether the next bytes can start a gzip member, judging by the ones available.
+    pub fn at_magic(&mut self) -> Result<bool> {
+        let magic = [ID1, ID2];
+        let buf = self.peek(magic.len())?;
+        Ok(magic.starts_with(buf))
+    }
+
     fn read_header(&mut self) -> Result<MemberHeader> {
-        let id1 = self.reader.read_u8().context("Failed reading ID1!")?;
-        let id2 = self.reader.read_u8().context("Failed reading ID1!")?;
+        let mut reader = HeaderReader::new(&mut self.reader);
+
+        let id1 = reader.read_u8().context("Failed reading ID1!")?;
+        let id2 = reader.read_u8().context("Failed reading ID1!")?;
         ensure!(id1 == ID1 && id2 == ID2, "wrong id values!");
 
         let compression_method =
-            CompressionMethod::from(self.reader.read_u8().context("Failed reading CM!")?);
+            CompressionMethod::from(reader.read_u8().context("Failed reading CM!")?);
 
-        let member_flags = MemberFlags(self.reader.read_u8().context("Failed reading FLG!")?);
+        let member_flags = MemberFlags(reader.read_u8().context("Failed reading FLG!")?);
 
         let header = MemberHeader {
             compression_method,
-            modification_time: self.read_modification_time()?,
-            extra_flags: self.reader.read_u8().context("Failed reading XFL!")?,
-            os: self.reader.read_u8().context("Failed reading OS!")?,
-            extra: self.read_extra(member_flags.has_extra())?,
-            name: self.read_name(member_flags.has_name())?,
-            comment: self.read_comment(member_flags.has_comment())?,
+            modification_time: reader.read_modification_time()?,
+            extra_flags: reader.read_u8().context("Failed reading XFL!")?,
+            os: reader.read_u8().context("Failed reading OS!")?,
+            extra: reader.read_extra(member_flags.has_extra(), self.lenient_extra_field)?,
+            name: reader.read_name(member_flags.has_name(), self.max_string_len)?,
+            comment: reader.read_comment(member_flags.has_comment(), self.max_string_len)?,
             has_crc: member_flags.has_crc(),
             is_text: member_flags.is_text(),
         };
 
         if member_flags.has_crc() {
+            let actual = reader.crc16();
             let crc16 = self
                 .reader
                 .read_u16::<LittleEndian>()
                 .context("Failed reading CRC16!")?;
 
-            ensure!(header.crc16() == crc16, "header crc16 check failed!");
+            if actual != crc16 {
+                let error = DecompressError::HeaderCrcMismatch {
+                    expected: crc16,
+                    actual,
+                };
+                ensure!(self.lenient_header_crc, error);
+                warn!("Ignoring {}", error);
+            }
         }
 
         Ok(header)
     }
+}
 
-    fn read_null_term_string(&mut self) -> Result<String> {
-        let mut buffer = Vec::new();
-        self.reader.read_until(0, &mut buffer)?;
+// reads the header fields, computing CRC32 of exactly the bytes taken from the input, so that
+// FHCRC is checked against them rather than against the fields serialized back
+struct HeaderReader<'a, R> {
+    reader: &'a mut R,
+    digest: Digest<'static, u32>,
+}
+
+impl<R: Read> Read for HeaderReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.reader.read(buf)?;
+        self.digest.update(&buf[..read]);
+        Ok(read)
+    }
+}
+
+impl<'a, R: BufRead> HeaderReader<'a, R> {
+    fn new(reader: &'a mut R) -> Self {
+        Self {
+            reader,
+            digest: CRC_ALGORITHM.digest(),
+        }
+    }
+
+    fn crc16(&self) -> u16 {
+        (self.digest.clone().finalize() & 0xffff) as u16
+    }
 
-        ensure!(!buffer.is_empty(), "No null-terminator!");
+    // bounded, so that a corrupted stream without NUL isn't read into memory as a whole
+    fn read_null_term_string(&mut self, field: &'static str, max_len: u64) -> Result<String> {
+        let mut buffer = Vec::new();
+        (&mut self.reader)
+            .take(max_len.saturating_add(1))
+            .read_until(0, &mut buffer)?;
+        self.digest.update(&buffer);
+
+        if buffer.last() != Some(&0) {
+            ensure!(
+                (buffer.len() as u64) <= max_len,
+                DecompressError::StringTooLong {
+                    field,
+                    limit: max_len
+                }
+            );
+            bail!(DecompressError::UnterminatedString { field });
+        }
+        buffer.pop();
 
-        Ok(String::from_utf8(buffer)?)
+        // ISO-8859-1 maps every byte to the char of the same code
+        Ok(buffer.into_iter().map(char::from).collect())
     }
 
     fn read_modification_time(&mut self) -> Result<u32> {
-        self.reader
-            .read_u32::<LittleEndian>()
+        self.read_u32::<LittleEndian>()
             .context("Failed reading MTIME!")
     }
 
-    fn read_extra(&mut self, has_extra: bool) -> Result<Option<Vec<u8>>> {
+    fn read_extra(&mut self, has_extra: bool, is_lenient: bool) -> Result<Option<Vec<u8>>> {
         if !has_extra {
             return Ok(None);
         }
 
         let len = self
-            .reader
             .read_u16::<LittleEndian>()
             .context("Failed reading XLEN!")?;
 
-        let mut buf = vec![0u8; len as usize];
-        self.reader
-            .read_exact(&mut buf)
+        // reading through take, so a corrupt XLEN doesn't allocate more than the input holds
+        let mut buf = Vec::new();
+        self.take(len as u64)
+            .read_to_end(&mut buf)
             .context("Failed to read extra field!")?;
 
+        if buf.len() < len as usize {
+            let error = DecompressError::InvalidExtraField { declared_len: len };
+            ensure!(is_lenient, error);
+            warn!("{}, keeping {} bytes", error, buf.len());
+        }
+
         Ok(Some(buf))
     }
 
-    fn read_name(&mut self, has_name: bool) -> Result<Option<String>> {
+    fn read_name(&mut self, has_name: bool, max_len: u64) -> Result<Option<String>> {
         if !has_name {
             return Ok(None);
         }
 
         Ok(Some(
-            self.read_null_term_string()
+            self.read_null_term_string("name", max_len)
                 .context("Failed reading file name!")?,
         ))
     }
 
-    fn read_comment(&mut self, has_comment: bool) -> Result<Option<String>> {
+    fn read_comment(&mut self, has_comment: bool, max_len: u64) -> Result<Option<String>> {
         if !has_comment {
             return Ok(None);
         }
 
         Ok(Some(
-            self.read_null_term_string()
+            self.read_null_term_string("comment", max_len)
                 .context("Failed reading comment!")?,
         ))
     }
@@ -297,14 +655,37 @@ impl<R: BufRead, W: Write> GzipReader<R, W> {
 
 ////////////////////////////////////////////////////////////////////////////////
 
+// ISIZE holds the size modulo 2^32, so members over 4GB can only be checked partially
+fn size_matches(byte_count: u64, data_size: u32) -> bool {
+    if byte_count > u32::MAX as u64 {
+        warn!(
+            "Member size {} doesn't fit into ISIZE, comparing it modulo 2^32",
+            byte_count
+        );
+    }
+
+    byte_count as u32 == data_size
+}
+
 pub struct GzipFooter<R, W> {
     reader: R,
     writer: TrackingWriter<W>,
+    lenient_length: bool,
 }
 
 impl<R: BufRead, W: Write> GzipFooter<R, W> {
     pub fn new(reader: R, writer: TrackingWriter<W>) -> Self {
-        GzipFooter { reader, writer }
+        GzipFooter {
+            reader,
+            writer,
+            lenient_length: false,
+        }
+    }
+
+    /// In lenient mode an ISIZE mismatch is only logged as a warning if CRC32 matches (so it
+    /// has to be computed), as written by some buggy compressors.
+    pub fn set_lenient_length(&mut self, lenient_length: bool) {
+        self.lenient_length = lenient_length;
     }
 
     pub fn read_footer(mut self) -> Result<(MemberFooter, GzipReader<R, W>)> {
@@ -323,16 +704,216 @@ impl<R: BufRead, W: Write> GzipFooter<R, W> {
             data_size,
         };
 
-        if self.writer.byte_count() != (footer.data_size as usize) {
-            bail!("length check failed!");
+        let crc_matches = self.writer.crc32().map(|crc32| crc32 == footer.data_crc32);
+
+        if !size_matches(self.writer.byte_count(), footer.data_size) {
+            let error = DecompressError::LengthMismatch {
+                expected: footer.data_size,
+                actual: self.writer.byte_count(),
+            };
+            ensure!(self.lenient_length && crc_matches == Some(true), error);
+            warn!("Ignoring {} as CRC32 matches", error);
         }
 
-        let (crc32, underlying) = self.writer.crc32();
+        match crc_matches {
+            Some(crc_matches) => ensure!(crc_matches, "crc32 check failed!"),
+            None => warn!("CRC32 isn't computed, skipping its check"),
+        }
 
-        if crc32 != footer.data_crc32 {
-            bail!("crc32 check failed!");
+        // history and checksum are per member, but the writer itself is reused
+        self.writer.reset();
+
+        Ok((footer, GzipReader::new(self.reader, self.writer)))
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header_with_name(name: Option<&str>) -> MemberHeader {
+        match name {
+            Some(name) => MemberHeader::builder().name(name).build(),
+            None => MemberHeader::builder().build(),
         }
+    }
+
+    #[test]
+    fn latin1_fields() {
+        let header = MemberHeader::builder()
+            .name("naïve")
+            .comment("файл")
+            .build();
+        assert_eq!(header.to_bytes()[10..], *b"na\xefve\0????\0");
+    }
+
+    #[test]
+    fn metadata_eq() {
+        let header = MemberHeader::builder()
+            .name("file.txt")
+            .modification_time(1)
+            .build();
+        let later = MemberHeader::builder()
+            .name("file.txt")
+            .modification_time(2)
+            .build();
+        assert!(header.metadata_eq(&header, false));
+        assert!(!header.metadata_eq(&later, false));
+        assert!(header.metadata_eq(&later, true));
+
+        let renamed = MemberHeader::builder()
+            .name("other.txt")
+            .modification_time(1)
+            .build();
+        assert!(!header.metadata_eq(&renamed, true));
+    }
 
-        Ok((footer, GzipReader::new(self.reader, underlying)))
+    #[test]
+    fn builder() {
+        let header = MemberHeader::builder()
+            .name("file.txt")
+            .comment("none")
+            .modification_time(1)
+            .has_crc(true)
+            .build();
+        assert_eq!(header.os, OS_UNKNOWN);
+        assert!(matches!(
+            header.compression_method,
+            CompressionMethod::Deflate
+        ));
+
+        let mut expected = vec![ID1, ID2, CM_DEFLATE, 0b11010, 1, 0, 0, 0, 0, OS_UNKNOWN];
+        expected.extend(b"file.txt\0none\0");
+        let crc16 = header.crc16();
+        expected.extend(crc16.to_le_bytes());
+        assert_eq!(header.to_bytes(), expected);
+    }
+
+    #[test]
+    fn flags() {
+        let flags = header_with_name(Some("file.txt")).flags();
+        assert!(flags.has_name());
+        assert!(!flags.has_extra() && !flags.has_comment() && !flags.has_crc());
+        assert_eq!(flags, MemberFlags(1 << FNAME_OFFSET));
+
+        let flags = header_with_name(None).flags();
+        assert!(!flags.has_name());
+        assert_eq!(flags, MemberFlags(0));
+    }
+
+    #[test]
+    fn labels() {
+        assert_eq!(CompressionMethod::Deflate.as_str(), "deflate");
+        assert_eq!(CompressionMethod::Unknown(7).as_str(), "unknown");
+        assert_eq!(CompressionMethod::Unknown(7).to_string(), "unknown(7)");
+        assert!(CompressionMethod::Unknown(7).is_reserved());
+        assert!(!CompressionMethod::Unknown(9).is_reserved());
+        assert!(!CompressionMethod::Deflate.is_reserved());
+
+        let mut header = header_with_name(None);
+        assert_eq!(header.os_label(), "unknown");
+        assert_eq!(header.extra_flags_label(), "none");
+
+        header.os = 3;
+        header.extra_flags = 2;
+        assert_eq!(header.os_label(), "Unix");
+        assert_eq!(header.extra_flags_label(), "maximum compression");
+
+        header.os = 42;
+        header.extra_flags = 1;
+        assert_eq!(header.os_label(), "undefined");
+        assert_eq!(header.extra_flags_label(), "undefined");
+    }
+
+    #[test]
+    fn likely_producer() {
+        let mut header = header_with_name(None);
+        assert_eq!(header.likely_producer(), Some("go"));
+
+        header.modification_time = 1700000000;
+        assert_eq!(header.likely_producer(), Some("python"));
+
+        header.os = 3;
+        assert_eq!(header.likely_producer(), Some("gzip"));
+
+        header.modification_time = 0;
+        assert_eq!(header.likely_producer(), Some("zlib"));
+
+        header.os = 0;
+        assert_eq!(header.likely_producer(), Some("java"));
+
+        header.extra = Some(vec![b'X', b'Y', 1, 0, 0, b'B', b'C', 2, 0, 0, 0]);
+        assert_eq!(header.likely_producer(), Some("bgzip"));
+
+        header.extra = Some(vec![b'X', b'Y', 1, 0, 0]);
+        assert_eq!(header.likely_producer(), None);
+    }
+
+    #[test]
+    fn truncated_extra_field() {
+        let input = [
+            ID1,
+            ID2,
+            CM_DEFLATE,
+            1 << 2,
+            0,
+            0,
+            0,
+            0,
+            0,
+            OS_UNKNOWN,
+            100,
+            0,
+            1,
+            2,
+            3,
+        ];
+
+        let mut reader = GzipReader::new(input.as_slice(), TrackingWriter::new(io::sink()));
+        let error = reader.next_header().unwrap_err();
+        assert_eq!(
+            error.downcast_ref(),
+            Some(&DecompressError::InvalidExtraField { declared_len: 100 })
+        );
+
+        let mut reader = GzipReader::new(input.as_slice(), TrackingWriter::new(io::sink()));
+        reader.set_lenient_extra_field(true);
+        let header = reader.next_header().unwrap();
+        assert_eq!(header.extra, Some(vec![1, 2, 3]));
+    }
+
+    #[test]
+    fn size_matches_wrapped() {
+        assert!(size_matches(10, 10));
+        assert!(!size_matches(10, 11));
+        assert!(size_matches((1 << 32) + 10, 10));
+        assert!(size_matches(5 << 32, 0));
+        assert!(!size_matches(u32::MAX as u64 + 1, u32::MAX));
+    }
+
+    #[test]
+    fn safe_name() {
+        assert_eq!(header_with_name(None).safe_name(), None);
+        assert_eq!(
+            header_with_name(Some("file.txt")).safe_name(),
+            Some("file.txt")
+        );
+        assert_eq!(header_with_name(Some("..file")).safe_name(), Some("..file"));
+
+        for name in [
+            "",
+            ".",
+            "..",
+            "../../etc/passwd",
+            "/etc/passwd",
+            "dir/file",
+            "..\\windows",
+            "C:\\file",
+            "C:file",
+        ] {
+            assert_eq!(header_with_name(Some(name)).safe_name(), None, "{name}");
+        }
     }
 }
@@ -1,59 +1,137 @@
 #![forbid(unsafe_code)]
 
-use std::{collections::HashMap, convert::TryFrom, io::BufRead};
+use std::{collections::HashMap, convert::TryFrom, io::BufRead, iter, sync::OnceLock};
 
 use anyhow::{anyhow, bail, ensure, Context, Result};
 
-use crate::bit_reader::{BitReader, BitSequence};
+use crate::{
+    bit_reader::{BitReader, BitSequence},
+    error::DecompressError,
+};
 
 ////////////////////////////////////////////////////////////////////////////////
 
-pub fn decode_litlen_distance_trees<T: BufRead>(
+// HLIT and HDIST can declare up to 288 and 32 codes, but the last two of each never occur
+const MAX_LITLEN_CODES: usize = 286;
+const MAX_DISTANCE_CODES: usize = 30;
+
+/// Code lengths declared in the header of a dynamic block.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DynamicCodeLengths {
+    pub litlen: Vec<u8>,
+    pub distance: Vec<u8>,
+}
+
+/// Header of a dynamic block as stored in the stream. HLIT, HDIST and HCLEN are the raw
+/// values, i.e. 257, 1 and 4 less than the number of codes they declare.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DynamicHeader {
+    pub hlit: u8,
+    pub hdist: u8,
+    pub hclen: u8,
+    /// Lengths of the code length alphabet, indexed by symbol (not in the stored order).
+    pub codelen_lengths: [u8; 19],
+    pub code_lengths: DynamicCodeLengths,
+}
+
+impl DynamicHeader {
+    /// Huffman table of the code length alphabet the code lengths of this block were read with.
+    pub fn codelen_coding(&self) -> Result<HuffmanCoding<TreeCodeToken>> {
+        build_codelen_coding(&self.codelen_lengths)
+    }
+}
+
+pub fn read_dynamic_code_lengths<T: BufRead>(
     bit_reader: &mut BitReader<T>,
-) -> Result<(HuffmanCoding<LitLenToken>, HuffmanCoding<DistanceToken>)> {
-    let litlen_codes_count = (bit_reader
+) -> Result<DynamicCodeLengths> {
+    Ok(read_dynamic_header(bit_reader)?.code_lengths)
+}
+
+/// Reads the header of a dynamic block right after BTYPE, without building its trees.
+pub fn read_dynamic_header<T: BufRead>(bit_reader: &mut BitReader<T>) -> Result<DynamicHeader> {
+    let hlit = bit_reader
         .read_bits(5)
         .context("Failed to read HLIT bits")?
-        .bits()
-        + 257) as usize;
-    let dist_codes_count = (bit_reader
+        .bits() as u8;
+    let hdist = bit_reader
         .read_bits(5)
         .context("Failed to read HDIST bits")?
-        .bits()
-        + 1) as usize;
-    let codelen_codes_count = bit_reader
+        .bits() as u8;
+    let hclen = bit_reader
         .read_bits(4)
         .context("Failed to read HCLEN bits")?
-        .bits()
-        + 4;
-
-    let codelen_coding = build_codelen_coding(bit_reader, codelen_codes_count)
-        .context("Failed to build codelen coding")?;
-
-    let mut code_lengths = Vec::<u8>::with_capacity(litlen_codes_count + dist_codes_count);
-    while code_lengths.len() < litlen_codes_count + dist_codes_count {
+        .bits() as u8;
+
+    let litlen_codes_count = hlit as usize + 257;
+    let dist_codes_count = hdist as usize + 1;
+    let codelen_codes_count = hclen as u16 + 4;
+    ensure!(
+        litlen_codes_count <= MAX_LITLEN_CODES && dist_codes_count <= MAX_DISTANCE_CODES,
+        DecompressError::InvalidBlockHeader(format!(
+            "{litlen_codes_count} literal/length and {dist_codes_count} distance codes declared, \
+             at most {MAX_LITLEN_CODES} and {MAX_DISTANCE_CODES} are allowed"
+        ))
+    );
+
+    let codelen_lengths = read_codelen_lengths(bit_reader, codelen_codes_count)?;
+    let codelen_coding = build_codelen_coding(&codelen_lengths)?;
+
+    let total_count = litlen_codes_count + dist_codes_count;
+    let mut code_lengths = Vec::<u8>::with_capacity(total_count);
+    while code_lengths.len() < total_count {
         let token = codelen_coding.read_symbol(bit_reader)?;
-        match token {
-            TreeCodeToken::Length(val) => code_lengths.push(val),
+        let (length, count) = match token {
+            TreeCodeToken::Length(val) => (val, 1),
             TreeCodeToken::CopyPrev => {
                 let offset = bit_reader.read_bits(2)?.bits() as usize;
                 let prev = *code_lengths.last().context("No code length to copy!")?;
-                code_lengths.extend(vec![prev; 3 + offset]);
+                (prev, 3 + offset)
             }
             TreeCodeToken::RepeatZero { base, extra_bits } => {
                 let offset = bit_reader.read_bits(extra_bits)?.bits() as usize;
-                code_lengths.extend(vec![0; (base as usize) + offset])
+                (0, base as usize + offset)
             }
-        }
-    }
+        };
 
-    if code_lengths.len() > litlen_codes_count + dist_codes_count {
-        bail!("Number of codes exceeded!");
+        // checked before extending, so a corrupted header can't grow the vector
+        ensure!(
+            code_lengths.len() + count <= total_count,
+            "Number of codes exceeded!"
+        );
+        code_lengths.extend(iter::repeat_n(length, count));
     }
 
+    let distance = code_lengths.split_off(litlen_codes_count);
+    Ok(DynamicHeader {
+        hlit,
+        hdist,
+        hclen,
+        codelen_lengths,
+        code_lengths: DynamicCodeLengths {
+            litlen: code_lengths,
+            distance,
+        },
+    })
+}
+
+/// Builds the Huffman table of the code length alphabet from its lengths indexed by symbol
+/// (as in `DynamicHeader::codelen_lengths`). Fails if the lengths don't form a valid code, e.g.
+/// are all zero.
+pub fn build_codelen_coding(codelen_lengths: &[u8; 19]) -> Result<HuffmanCoding<TreeCodeToken>> {
+    // the code lengths that follow can't be read without any code
+    ensure!(
+        codelen_lengths.iter().any(|&len| len != 0),
+        DecompressError::InvalidBlockHeader("all code length code lengths are zero".to_string())
+    );
+    HuffmanCoding::from_lengths(codelen_lengths).context("Failed to build codelen coding")
+}
+
+pub fn build_dynamic_trees(
+    code_lengths: &DynamicCodeLengths,
+) -> Result<(HuffmanCoding<LitLenToken>, HuffmanCoding<DistanceToken>)> {
     Ok((
-        HuffmanCoding::from_lengths(&code_lengths[0..litlen_codes_count])?,
-        HuffmanCoding::from_lengths(&code_lengths[litlen_codes_count..])?,
+        HuffmanCoding::from_lengths(&code_lengths.litlen)?,
+        HuffmanCoding::from_lengths(&code_lengths.distance)?,
     ))
 }
 
@@ -65,12 +143,22 @@ pub fn build_fixed_trees() -> Result<(HuffmanCoding<LitLenToken>, HuffmanCoding<
 
     let distance_tree_lengths = [5u8; 32];
 
+    // literal/length symbols 286, 287 and distance symbols 30, 31 take part
+    // in the fixed code construction, but never occur in compressed data
     Ok((
-        HuffmanCoding::from_lengths(&litlen_tree_lengths)?,
-        HuffmanCoding::from_lengths(&distance_tree_lengths)?,
+        HuffmanCoding::from_lengths_with_reserved(&litlen_tree_lengths, 2)?,
+        HuffmanCoding::from_lengths_with_reserved(&distance_tree_lengths, 2)?,
     ))
 }
 
+type FixedTrees = (HuffmanCoding<LitLenToken>, HuffmanCoding<DistanceToken>);
+
+/// Fixed trees are the same for every block, so they're built only once.
+pub fn fixed_trees() -> &'static FixedTrees {
+    static FIXED_TREES: OnceLock<FixedTrees> = OnceLock::new();
+    FIXED_TREES.get_or_init(|| build_fixed_trees().expect("fixed code lengths are valid"))
+}
+
 fn read_codelen_length<T: BufRead>(bit_reader: &mut BitReader<T>) -> Result<u8> {
     Ok(bit_reader
         .read_bits(3)
@@ -78,10 +166,10 @@ fn read_codelen_length<T: BufRead>(bit_reader: &mut BitReader<T>) -> Result<u8>
         .bits() as u8)
 }
 
-fn build_codelen_coding<T: BufRead>(
+fn read_codelen_lengths<T: BufRead>(
     bit_reader: &mut BitReader<T>,
     codelen_codes_count: u16,
-) -> Result<HuffmanCoding<TreeCodeToken>> {
+) -> Result<[u8; 19]> {
     let mut codelen_code_lengths = [0u8; 19];
     codelen_code_lengths[16] = read_codelen_length(bit_reader)?;
     codelen_code_lengths[17] = read_codelen_length(bit_reader)?;
@@ -93,15 +181,20 @@ fn build_codelen_coding<T: BufRead>(
         codelen_code_lengths[j] = read_codelen_length(bit_reader)?
     }
 
-    HuffmanCoding::<TreeCodeToken>::from_lengths(&codelen_code_lengths)
+    Ok(codelen_code_lengths)
 }
 
 ////////////////////////////////////////////////////////////////////////////////
 
-#[derive(Clone, Copy, Debug)]
+/// Symbol of the code length alphabet (RFC 1951, 3.2.7).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum TreeCodeToken {
+    /// Symbols 0-15: a code length as is
     Length(u8),
+    /// Symbol 16: the previous length repeated 3-6 times, by 2 extra bits
     CopyPrev,
+    /// Symbols 17 and 18: zero repeated `base` plus the value of `extra_bits` times
+    /// (3-10 and 11-138)
     RepeatZero { base: u16, extra_bits: u8 },
 }
 
@@ -213,78 +306,136 @@ pub struct HuffmanCodeWord(pub u16);
 
 pub struct HuffmanCoding<T> {
     map: HashMap<BitSequence, T>,
+    code_lengths: Vec<u8>,
+}
+
+/// Assigns canonical Huffman codes to symbols by their code lengths
+/// (symbols with zero length get no code).
+pub fn canonical_codes(code_lengths: &[u8]) -> Result<Vec<Option<BitSequence>>> {
+    if code_lengths
+        .iter()
+        .max()
+        .is_some_and(|&x| x as usize > MAX_BITS)
+    {
+        bail!("Length greater than {MAX_BITS} found!");
+    }
+
+    let mut bl_count = [0usize; MAX_BITS + 1];
+    for &length in code_lengths {
+        bl_count[length as usize] += 1;
+    }
+
+    let mut code = 0;
+    bl_count[0] = 0;
+    let mut next_code = [0; MAX_BITS + 1];
+
+    for length in 1..=MAX_BITS {
+        code = (code + bl_count[length - 1]) << 1;
+        next_code[length] = code;
+    }
+
+    let mut codes = Vec::with_capacity(code_lengths.len());
+
+    for &length in code_lengths {
+        if length == 0 {
+            codes.push(None);
+            continue;
+        }
+
+        ensure!(
+            next_code[length as usize] < (1 << (length + 1)),
+            "Couldn't build coding, incorrect lengths provided!"
+        );
+
+        codes.push(Some(BitSequence::new(
+            next_code[length as usize] as u16,
+            length,
+        )));
+        next_code[length as usize] += 1;
+    }
+
+    Ok(codes)
 }
 
 impl<T> HuffmanCoding<T>
 where
     T: Copy + TryFrom<HuffmanCodeWord, Error = anyhow::Error>,
 {
-    pub fn new(map: HashMap<BitSequence, T>) -> Self {
-        Self { map }
+    fn new(map: HashMap<BitSequence, T>, code_lengths: Vec<u8>) -> Self {
+        Self { map, code_lengths }
     }
 
-    #[allow(unused)]
     pub fn decode_symbol(&self, seq: BitSequence) -> Option<T> {
         self.map.get(&seq).copied()
     }
 
+    /// Number of symbols having a code.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Code lengths the coding was built from, passing them back to `from_lengths`
+    /// (`from_lengths_with_reserved` for fixed trees) rebuilds the same coding.
+    /// Useful for caching trees.
+    pub fn to_lengths(&self) -> &[u8] {
+        &self.code_lengths
+    }
+
+    /// Length of the longest code, 0 for an empty coding.
+    pub fn max_code_length(&self) -> u8 {
+        self.map.keys().map(|code| code.len()).max().unwrap_or(0)
+    }
+
     pub fn read_symbol<U: BufRead>(&self, bit_reader: &mut BitReader<U>) -> Result<T> {
+        // e.g. a dynamic block with only literals has no distance codes
+        ensure!(!self.map.is_empty(), DecompressError::EmptyCodeTable);
+
         let mut code = BitSequence::new(0, 0);
         for _ in 0..MAX_BITS {
-            let new_bit = bit_reader.read_bits(1).context("Failed to read a bit")?;
+            // the io::Error is kept in the chain, so running out of input is a truncation
+            let new_bit = bit_reader.read_bits(1).with_context(|| {
+                format!(
+                    "Input ended after {} bits of a symbol: {:0width$b}",
+                    code.len(),
+                    code.bits(),
+                    width = code.len() as usize
+                )
+            })?;
             code = code.concat(new_bit);
             if let Some(&token) = self.map.get(&code) {
                 return Ok(token);
             }
         }
 
-        bail!("Failed to read a symbol");
+        bail!(DecompressError::InvalidSymbol { bits: code });
     }
 
     pub fn from_lengths(code_lengths: &[u8]) -> Result<Self> {
-        if code_lengths
-            .iter()
-            .max()
-            .is_some_and(|&x| x as usize > MAX_BITS)
-        {
-            bail!("Length greater than {MAX_BITS} found!");
-        }
-
-        let mut bl_count = [0usize; MAX_BITS + 1];
-        for &length in code_lengths {
-            bl_count[length as usize] += 1;
-        }
-
-        let mut code = 0;
-        bl_count[0] = 0;
-        let mut next_code = [0; MAX_BITS + 1];
+        Self::from_lengths_with_reserved(code_lengths, 0)
+    }
 
-        for length in 1..=MAX_BITS {
-            code = (code + bl_count[length - 1]) << 1;
-            next_code[length] = code;
-        }
+    /// Same as `from_lengths`, but the last `reserved_count` symbols only get their codes
+    /// allocated and are left out of the map.
+    pub fn from_lengths_with_reserved(code_lengths: &[u8], reserved_count: usize) -> Result<Self> {
+        let codes = canonical_codes(code_lengths)?;
+        let mapped_count = code_lengths.len().saturating_sub(reserved_count);
 
         let mut map = HashMap::new();
 
-        for (i, &length) in code_lengths.iter().enumerate() {
-            if length != 0 {
-                ensure!(
-                    next_code[length as usize] < (1 << (length + 1)),
-                    "Couldn't build coding, incorrect lengths provided!"
-                );
-
-                let bits = next_code[length as usize] as u16;
-
+        for (i, &code) in codes.iter().enumerate().take(mapped_count) {
+            if let Some(code) = code {
                 let word = HuffmanCodeWord(u16::try_from(i).context("code_lengths is too large!")?);
                 let token = T::try_from(word).context("Couldn't create a token from word!")?;
 
-                map.insert(BitSequence::new(bits, length), token);
-
-                next_code[length as usize] += 1;
+                map.insert(code, token);
             }
         }
 
-        Ok(HuffmanCoding::new(map))
+        Ok(HuffmanCoding::new(map, code_lengths.to_vec()))
     }
 }
 
@@ -293,10 +444,102 @@ where
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::io;
 
     #[derive(Clone, Copy, Debug, PartialEq)]
     struct Value(u16);
 
+    fn code_counts_error(data: &[u8]) -> Option<DecompressError> {
+        let mut reader = BitReader::new(data);
+        let error = read_dynamic_code_lengths(&mut reader).unwrap_err();
+        error.downcast_ref::<DecompressError>().cloned()
+    }
+
+    // the largest extra bits of the last codes reach exactly the format limits
+    #[test]
+    fn token_ranges() -> Result<()> {
+        let LitLenToken::Length { base, extra_bits } = HuffmanCodeWord(284).try_into()? else {
+            panic!("284 is a length code");
+        };
+        assert_eq!(base + ((1 << extra_bits) - 1), 258);
+
+        let distance = DistanceToken::try_from(HuffmanCodeWord(29))?;
+        assert_eq!(
+            distance.base as u32 + ((1 << distance.extra_bits) - 1),
+            32768
+        );
+
+        assert!(DistanceToken::try_from(HuffmanCodeWord(30)).is_err());
+        assert!(LitLenToken::try_from(HuffmanCodeWord(286)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn repeat_past_code_count() {
+        // 257 + 1 codes declared, codelen codes 0 and 18 of length 1,
+        // then 18 with 127 in extra bits twice: 138 + 138 zeros
+        let data: &[u8] = &[0, 0, 0b10010000, 0b11111100, 0b11111111, 0b11];
+        let mut reader = BitReader::new(data);
+
+        let error = read_dynamic_code_lengths(&mut reader).unwrap_err();
+        assert!(error.to_string().contains("Number of codes exceeded"));
+    }
+
+    #[test]
+    fn dynamic_header() -> Result<()> {
+        // a dynamic block compressed by zlib, BFINAL and BTYPE take the first 3 bits
+        let data: &[u8] = &[
+            61, 142, 137, 13, 0, 48, 8, 2, 103, 245, 220, 127, 135, 242, 152, 54, 70, 17, 208, 58,
+            236, 142, 158, 18, 19, 36, 32, 10, 23, 103, 247, 110, 28, 212, 246, 173,
+        ];
+        let mut reader = BitReader::new(data);
+        assert_eq!(reader.read_bits(3)?, BitSequence::new(0b101, 3));
+
+        let header = read_dynamic_header(&mut reader)?;
+        assert_eq!((header.hlit, header.hdist, header.hclen), (7, 14, 12));
+        assert_eq!(header.code_lengths.litlen.len(), 264);
+        assert_eq!(header.code_lengths.distance.len(), 15);
+        assert!(header.codelen_lengths.iter().any(|&len| len != 0));
+        build_dynamic_trees(&header.code_lengths)?;
+
+        let codelen_coding = header.codelen_coding()?;
+        assert_eq!(codelen_coding.to_lengths(), header.codelen_lengths);
+        // every symbol with a code decodes to its token
+        let codes = canonical_codes(&header.codelen_lengths)?;
+        for (symbol, code) in codes.into_iter().enumerate() {
+            if let Some(code) = code {
+                assert_eq!(
+                    codelen_coding.decode_symbol(code),
+                    Some(TreeCodeToken::try_from(HuffmanCodeWord(symbol as u16))?)
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn too_many_codes() {
+        // HLIT = 31: 288 literal/length codes
+        assert!(matches!(
+            code_counts_error(&[0b00011111, 0, 0, 0]),
+            Some(DecompressError::InvalidBlockHeader(_))
+        ));
+        // HDIST = 31: 32 distance codes
+        assert!(matches!(
+            code_counts_error(&[0b11100000, 0b00000011, 0, 0]),
+            Some(DecompressError::InvalidBlockHeader(_))
+        ));
+        // HLIT = 29, HDIST = 29 are the largest valid counts, it's the all-zero code length
+        // code lengths that fail
+        let Some(DecompressError::InvalidBlockHeader(reason)) =
+            code_counts_error(&[0b10111101, 0b00000011, 0, 0])
+        else {
+            panic!("empty code length code is an invalid header");
+        };
+        assert!(reason.contains("code length code lengths are zero"));
+    }
+
     impl TryFrom<HuffmanCodeWord> for Value {
         type Error = anyhow::Error;
 
@@ -345,6 +588,39 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn len() -> Result<()> {
+        let code = HuffmanCoding::<Value>::from_lengths(&[2, 3, 0, 3, 3, 4, 2])?;
+        assert_eq!(code.len(), 6);
+        assert_eq!(code.max_code_length(), 4);
+
+        let empty = HuffmanCoding::<Value>::from_lengths(&[0, 0])?;
+        assert!(empty.is_empty());
+        assert_eq!(empty.max_code_length(), 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn to_lengths() -> Result<()> {
+        let lengths = [3, 4, 5, 5, 0, 0, 6, 6, 4, 0, 6, 0, 7, 0];
+        let code = HuffmanCoding::<Value>::from_lengths(&lengths)?;
+        assert_eq!(code.to_lengths(), lengths);
+
+        let rebuilt = HuffmanCoding::<Value>::from_lengths(code.to_lengths())?;
+        for length in 1..=7 {
+            for bits in 0..(1 << length) {
+                let seq = BitSequence::new(bits, length);
+                assert_eq!(rebuilt.decode_symbol(seq), code.decode_symbol(seq));
+            }
+        }
+
+        let (litlen_tree, _) = build_fixed_trees()?;
+        assert_eq!(litlen_tree.to_lengths().len(), 288);
+
+        Ok(())
+    }
+
     #[test]
     fn read_symbol() -> Result<()> {
         let code = HuffmanCoding::<Value>::from_lengths(&[2, 3, 4, 3, 3, 4, 2])?;
@@ -363,6 +639,71 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn fixed_trees() -> Result<()> {
+        let (litlen_tree, distance_tree) = build_fixed_trees()?;
+        let mut data: &[u8] = &[0b00001100, 0b11111111, 0b01000111, 0b00101111];
+        let mut reader = BitReader::new(&mut data);
+
+        assert!(matches!(
+            litlen_tree.read_symbol(&mut reader)?,
+            LitLenToken::Literal(0)
+        ));
+        assert!(matches!(
+            litlen_tree.read_symbol(&mut reader)?,
+            LitLenToken::Literal(255)
+        ));
+        assert!(matches!(
+            litlen_tree.read_symbol(&mut reader)?,
+            LitLenToken::Length {
+                base: 258,
+                extra_bits: 0
+            }
+        ));
+        assert_eq!(distance_tree.read_symbol(&mut reader)?.base, 24577);
+
+        Ok(())
+    }
+
+    #[test]
+    fn read_symbol_errors() -> Result<()> {
+        // the only code is 0, so a run of ones never matches
+        let code = HuffmanCoding::<Value>::from_lengths(&[1])?;
+
+        let mut reader = BitReader::new([0xffu8; 2].as_slice());
+        let error = code.read_symbol(&mut reader).unwrap_err();
+        assert_eq!(
+            error.downcast_ref(),
+            Some(&DecompressError::InvalidSymbol {
+                bits: BitSequence::new(0x7fff, 15)
+            })
+        );
+
+        let mut reader = BitReader::new([0xffu8].as_slice());
+        let error = code.read_symbol(&mut reader).unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("after 8 bits of a symbol: 11111111"));
+        assert_eq!(
+            error.downcast_ref::<io::Error>().map(io::Error::kind),
+            Some(io::ErrorKind::UnexpectedEof)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn all_zero_lengths() -> Result<()> {
+        let code = HuffmanCoding::<DistanceToken>::from_lengths(&[0])?;
+        let mut reader = BitReader::new([0xffu8; 4].as_slice());
+
+        let error = code.read_symbol(&mut reader).unwrap_err();
+        assert_eq!(error.downcast_ref(), Some(&DecompressError::EmptyCodeTable));
+        assert_eq!(reader.position(), 0);
+
+        Ok(())
+    }
+
     #[test]
     fn from_lengths_with_zeros() -> Result<()> {
         let lengths = [3, 4, 5, 5, 0, 0, 6, 6, 4, 0, 6, 0, 7];
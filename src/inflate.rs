@@ -0,0 +1,130 @@
+#![forbid(unsafe_code)]
+
+use std::{
+    io::{BufRead, Write},
+    mem,
+};
+
+use anyhow::{bail, Result};
+
+use crate::{
+    bit_reader::BitReader,
+    deflate::{
+        BlockHeader, DeflateBlock, DeflateReader,
+        NextBlock::{BlockOrError, Footer},
+    },
+    tracking_writer::TrackingWriter,
+};
+
+////////////////////////////////////////////////////////////////////////////////
+
+enum State<R, W> {
+    Decoding(DeflateReader<R, W>),
+    // a block that ran out of input in the middle
+    InBlock(DeflateBlock<R, W>),
+    Finished(R, TrackingWriter<W>),
+    // an error happened in the middle of a block
+    Failed,
+}
+
+/// Inflate core without any framing, for building custom containers on top of raw deflate.
+///
+/// Blocks are decoded one at a time with `next_block`. Once the final block is decoded,
+/// `into_inner` gives back the input positioned at the first byte after the deflate stream
+/// and the output. After an error the state can't be used anymore, except for `WouldBlock` of
+/// an input that isn't filled yet (e.g. `GrowableInput` before a push): then `next_block` can be
+/// called again once more input arrives, and it continues from where the input ended, even in
+/// the middle of a block.
+pub struct InflateState<R, W> {
+    state: State<R, W>,
+}
+
+impl<R: BufRead, W: Write> InflateState<R, W> {
+    pub fn new(input: R, output: W) -> Self {
+        // integrity checks are up to the framing, so CRC32 isn't computed
+        let mut writer = TrackingWriter::new(output);
+        writer.set_crc_enabled(false);
+        let mut deflate_reader = DeflateReader::new(BitReader::new(input), writer);
+        deflate_reader.set_resumable(true);
+
+        Self {
+            state: State::Decoding(deflate_reader),
+        }
+    }
+
+    /// Decodes the next block to the output and returns its header, or `None` if the final
+    /// block has already been decoded.
+    pub fn next_block(&mut self) -> Result<Option<BlockHeader>> {
+        let mut block = match mem::replace(&mut self.state, State::Failed) {
+            // the state becomes finished right after the final block, so there's one more
+            State::Decoding(mut deflate_reader) => match deflate_reader.read_block_start() {
+                Ok((header, code_lengths)) => deflate_reader.into_block(header, code_lengths),
+                Err(error) => {
+                    if deflate_reader.is_waiting_for_input() {
+                        self.state = State::Decoding(deflate_reader);
+                    }
+                    return Err(error);
+                }
+            },
+            State::InBlock(block) => block,
+            finished @ State::Finished(..) => {
+                self.state = finished;
+                return Ok(None);
+            }
+            State::Failed => bail!("Inflate state is unusable after an error!"),
+        };
+
+        if let Err(error) = block.decode(None) {
+            if block.is_waiting_for_input() {
+                self.state = State::InBlock(block);
+            }
+            return Err(error);
+        }
+        let header = block.get_header().clone();
+        let deflate_reader = block.into_reader();
+
+        self.state = if header.is_final {
+            match deflate_reader.next_block() {
+                Footer(input, writer, _) => State::Finished(input, writer),
+                BlockOrError(_) => unreachable!(),
+            }
+        } else {
+            State::Decoding(deflate_reader)
+        };
+
+        Ok(Some(header))
+    }
+
+    /// Input to extend with more data (e.g. with `GrowableInput::push`), `None` after an error.
+    pub fn input_mut(&mut self) -> Option<&mut R> {
+        match &mut self.state {
+            State::Decoding(deflate_reader) => Some(deflate_reader.get_reader_mut()),
+            State::InBlock(block) => Some(block.get_reader_mut()),
+            State::Finished(input, _) => Some(input),
+            State::Failed => None,
+        }
+    }
+
+    /// Whether the final block has been decoded.
+    pub fn is_finished(&self) -> bool {
+        matches!(self.state, State::Finished(..))
+    }
+
+    /// Number of bytes written to the output so far, zero after an error.
+    pub fn output_size(&self) -> u64 {
+        match &self.state {
+            State::Decoding(deflate_reader) => deflate_reader.get_writer().byte_count(),
+            State::InBlock(block) => block.get_writer().byte_count(),
+            State::Finished(_, writer) => writer.byte_count(),
+            State::Failed => 0,
+        }
+    }
+
+    /// Input and output of a finished stream.
+    pub fn into_inner(self) -> Result<(R, W)> {
+        match self.state {
+            State::Finished(input, writer) => Ok((input, writer.into_inner())),
+            _ => bail!("Deflate stream hasn't ended!"),
+        }
+    }
+}
@@ -1,8 +1,13 @@
 #![forbid(unsafe_code)]
 
-use std::io::{BufRead, Write};
+use std::{
+    collections::VecDeque,
+    io::{self, BufRead, BufReader, BufWriter, Read, Write},
+    mem,
+    time::Instant,
+};
 
-use anyhow::Result;
+use anyhow::{bail, ensure, Context as _, Result};
 use deflate::{
     DeflateBlock, DeflateReader,
     NextBlock::{BlockOrError, Footer},
@@ -10,26 +15,169 @@ use deflate::{
 use gzip::GzipFooter;
 use log::*;
 
-use gzip::GzipReader;
+use counting_reader::CountingReader;
+use crc_writer::Crc32Writer;
+use gzip::{GzipReader, ID1, ID2};
+use newline_writer::NewlineNormalizer;
+use tracking_writer::TrackingWriter;
+
+pub use bit_reader::{BitCheckpoint, BitReader, BitSequence, Bits};
+pub use block_index::{build_block_index, BlockOffset};
+pub use block_trees::{BlockTrees, CodeEntry};
+pub use checksum::{Adler32, Checksum, Crc32};
+pub use chunks::{decompress_chunks, OUTPUT_CHUNK_SIZE};
+pub use decompressor::Decompressor;
+pub use deflate::{BlockHeader, CompressionType, Lz77Token, MAX_WINDOW_SIZE};
+pub use error::DecompressError;
+pub use extract::extract_all_to_dir;
+pub use growable_input::GrowableInput;
+pub use gzip::{
+    CompressionMethod, MemberFlags, MemberFooter, MemberHeader, MemberHeaderBuilder,
+    DEFAULT_MAX_STRING_LEN,
+};
+pub use huffman_coding::{
+    build_codelen_coding, read_dynamic_header, DistanceToken, DynamicCodeLengths, DynamicHeader,
+    HuffmanCodeWord, HuffmanCoding, LitLenToken, TreeCodeToken,
+};
+pub use inflate::InflateState;
+pub use lines::gzip_lines;
+pub use newline_writer::LineEnding;
+pub use null_writer::NullWriter;
+pub use observer::Observer;
+pub use options::{DecompressOptions, TextMode};
+#[cfg(feature = "pipelined")]
+pub use pipelined::decompress_pipelined;
+pub use prefix::decompress_prefix;
+#[cfg(feature = "recompress")]
+pub use recompress::recompress_matches;
+pub use rewrite::{rewrite_headers, rewrite_headers_with_options, RewriteOptions};
+pub use split::split_members;
+pub use stats::{
+    BlockTypeCounts, BlockTypeStats, DecompressStats, DistanceHistogram, MemberInfo, SkippedMember,
+};
+pub use tee_writer::TeeWriter;
+pub use transform_writer::TransformWriter;
+pub use zlib::{adler32, decompress_zlib, ZlibDecoder};
 
 mod bit_reader;
+mod block_index;
+mod block_trees;
+mod checksum;
+mod chunks;
+mod counting_reader;
+mod crc_writer;
+mod decompressor;
 mod deflate;
+mod error;
+mod extract;
+mod growable_input;
 mod gzip;
 mod huffman_coding;
+mod inflate;
+mod lines;
+mod members;
+mod newline_writer;
+mod null_writer;
+mod observer;
+mod options;
+#[cfg(feature = "pipelined")]
+mod pipelined;
+mod prefix;
+#[cfg(feature = "recompress")]
+mod recompress;
+mod rewrite;
+mod split;
+mod stats;
+mod tee_reader;
+mod tee_writer;
 mod tracking_writer;
+mod transform_writer;
+mod zlib;
+
+/// Size of the buffer `decompress_reader` puts in front of its input.
+pub const INPUT_BUFFER_SIZE: usize = 1 << 16;
+
+// how many bytes of non-gzip trailing data are shown in the error
+const TRAILING_PEEK_LEN: usize = 8;
+
+// shorter-lived callback for one call, e.g. for every pass of a loop
+fn reborrow_on_token<'a>(
+    on_token: &'a mut Option<&mut dyn FnMut(Lz77Token)>,
+) -> Option<&'a mut dyn FnMut(Lz77Token)> {
+    match on_token {
+        Some(on_token) => Some(&mut **on_token),
+        None => None,
+    }
+}
+
+// state shared by the pipeline functions during one decompression
+struct Context<'a> {
+    options: &'a DecompressOptions,
+    on_token: Option<&'a mut dyn FnMut(Lz77Token)>,
+    block_trees: Vec<BlockTrees>,
+    member_output_size: u64,
+    member_crc32: Option<u32>,
+    member_padding: BitSequence,
+    block_types: BlockTypeCounts,
+    distances: Option<DistanceHistogram>,
+    reported_input: u64,
+    reported_output: u64,
+}
+
+impl<'a> Context<'a> {
+    fn new(options: &'a DecompressOptions, on_token: Option<&'a mut dyn FnMut(Lz77Token)>) -> Self {
+        Self {
+            options,
+            on_token,
+            block_trees: Vec::new(),
+            member_output_size: 0,
+            member_crc32: None,
+            member_padding: BitSequence::new(0, 0),
+            block_types: BlockTypeCounts::default(),
+            distances: options.collect_distances.then(DistanceHistogram::default),
+            reported_input: 0,
+            reported_output: 0,
+        }
+    }
+
+    fn observer(&self) -> Option<&dyn Observer> {
+        self.options.observer.as_deref()
+    }
+
+    // output_count is counted from the start of the current member
+    fn report_bytes(&mut self, input_position: u64, output_count: u64) {
+        if let Some(observer) = self.observer() {
+            observer.on_bytes(
+                input_position - self.reported_input,
+                output_count - self.reported_output,
+            );
+        }
+
+        self.reported_input = input_position;
+        self.reported_output = output_count;
+    }
+}
 
 fn process_gzip_footer<R: BufRead, W: Write>(
-    gzip_footer: GzipFooter<R, W>,
-) -> Result<GzipReader<R, W>> {
+    gzip_footer: GzipFooter<CountingReader<R>, W>,
+    context: &mut Context,
+) -> Result<(MemberFooter, GzipReader<CountingReader<R>, W>)> {
     info!("Processing Gzip footer...");
 
     match gzip_footer.read_footer() {
         Ok((footer, gzip_reader)) => {
             trace!("Gzip footer: {:?}", footer);
 
+            context.report_bytes(gzip_reader.get_reader().position(), context.reported_output);
+            context.reported_output = 0;
+
+            if let Some(observer) = context.observer() {
+                observer.on_member_end(&footer);
+            }
+
             info!("Finished reading Gzip footer!");
 
-            Ok(gzip_reader)
+            Ok((footer, gzip_reader))
         }
 
         Err(error) => {
@@ -41,12 +189,61 @@ fn process_gzip_footer<R: BufRead, W: Write>(
 }
 
 fn process_deflate_block<R: BufRead, W: Write>(
-    block: DeflateBlock<R, W>,
-) -> Result<DeflateReader<R, W>> {
+    block: DeflateBlock<CountingReader<R>, W>,
+    context: &mut Context,
+) -> Result<DeflateReader<CountingReader<R>, W>> {
     trace!("Deflate block header: {:?}", block.get_header());
 
-    match block.read_content() {
-        Ok(deflate_reader) => {
+    if let Some(observer) = context.observer() {
+        observer.on_block(block.get_header());
+    }
+
+    if let Some(code_lengths) = block.get_code_lengths() {
+        if context.options.collect_block_trees {
+            context
+                .block_trees
+                .push(BlockTrees::from_code_lengths(code_lengths)?);
+        }
+    }
+
+    let compression_type = block.get_header().compression_type;
+    let output_start = block.get_writer().byte_count();
+
+    let content = match (&mut context.distances, &mut context.on_token) {
+        (Some(distances), on_token) => block.read_content(Some(&mut |token| {
+            if let Lz77Token::Match { distance, .. } = token {
+                distances.add(distance);
+            }
+            if let Some(on_token) = on_token {
+                on_token(token);
+            }
+        })),
+        (None, on_token) => block.read_content(reborrow_on_token(on_token)),
+    };
+
+    match content {
+        Ok(mut deflate_reader) => {
+            // empty unless recording was enabled for this member
+            let compressed_data = deflate_reader.take_recorded_input();
+            if let Some(observer) = context.observer() {
+                if !compressed_data.is_empty() {
+                    observer.on_compressed_data(&compressed_data);
+                }
+            }
+            if context.options.output_buffer_size.is_some() {
+                deflate_reader
+                    .writer_mut()
+                    .flush()
+                    .context("Failed to flush output!")?;
+            }
+
+            let output_size = deflate_reader.get_writer().byte_count() - output_start;
+            context.block_types.add(compression_type, output_size);
+            context.report_bytes(
+                deflate_reader.get_reader().position(),
+                deflate_reader.get_writer().byte_count(),
+            );
+
             info!("Finished reading deflate block!");
 
             Ok(deflate_reader)
@@ -60,15 +257,25 @@ fn process_deflate_block<R: BufRead, W: Write>(
 }
 
 fn process_compressed_data<R: BufRead, W: Write>(
-    mut deflate_reader: DeflateReader<R, W>,
-) -> Result<GzipReader<R, W>> {
+    mut deflate_reader: DeflateReader<CountingReader<R>, W>,
+    context: &mut Context,
+) -> Result<(MemberFooter, GzipReader<CountingReader<R>, W>)> {
     info!("Starting to process Deflate part of file...");
 
     loop {
+        if context
+            .options
+            .deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            error!("Decompression deadline exceeded!");
+            bail!(DecompressError::Timeout);
+        }
+
         match deflate_reader.next_block() {
             BlockOrError(maybe_block) => match maybe_block {
                 Ok(block) => {
-                    deflate_reader = process_deflate_block(block)?;
+                    deflate_reader = process_deflate_block(block, context)?;
                 }
 
                 Err(error) => {
@@ -78,38 +285,561 @@ fn process_compressed_data<R: BufRead, W: Write>(
                 }
             },
 
-            Footer(reader, writer) => {
-                return process_gzip_footer(GzipFooter::new(reader, writer));
+            Footer(reader, writer, padding) => {
+                context.member_padding = padding;
+                context.member_output_size = writer.byte_count();
+                context.member_crc32 = writer.crc32();
+                let mut gzip_footer = GzipFooter::new(reader, writer);
+                gzip_footer.set_lenient_length(context.options.lenient_length);
+                return process_gzip_footer(gzip_footer, context);
             }
         }
     }
 }
 
-pub fn decompress<R: BufRead, W: Write>(input: R, output: W) -> Result<()> {
-    let mut gzip_reader = GzipReader::new(input, output);
+// whether the error is caused by the input ending too early rather than by corrupted data
+fn is_truncation(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| {
+        cause
+            .downcast_ref::<io::Error>()
+            .is_some_and(|error| error.kind() == io::ErrorKind::UnexpectedEof)
+    })
+}
 
-    info!("Decompression started!");
-    while !gzip_reader.is_empty()? {
-        info!("Starting to process member...");
+// the length of a member compressed with an unknown method can't be found,
+// so all the remaining input is treated as its payload
+fn pass_through_raw<R: BufRead>(
+    reader: &mut R,
+    header: &MemberHeader,
+    context: &Context,
+) -> Result<()> {
+    loop {
+        let buf = reader
+            .fill_buf()
+            .context("Failed to read raw member payload!")?;
+        if buf.is_empty() {
+            return Ok(());
+        }
+
+        if let Some(observer) = context.observer() {
+            observer.on_raw_payload(header, buf);
+        }
+
+        let len = buf.len();
+        reader.consume(len);
+    }
+}
+
+/// Returns the total number of decompressed bytes.
+pub fn decompress<R: BufRead, W: Write>(input: R, output: W) -> Result<u64> {
+    decompress_with_options(input, output, &DecompressOptions::default())
+        .map(|stats| stats.output_size())
+}
+
+/// Same as `decompress`, but gives the output writer back, e.g. to take a `Vec` out of it.
+pub fn decompress_returning<R: BufRead, W: Write>(input: R, output: W) -> Result<W> {
+    let writer = TrackingWriter::new(NewlineNormalizer::new(output));
+    let (_, writer) = decompress_members(input, writer, &DecompressOptions::default(), None)?;
+
+    // only a truncated final member, which isn't accepted by default, loses the writer
+    let writer = writer.context("Output writer was lost!")?;
+    Ok(writer.into_inner().into_inner())
+}
+
+/// Decompresses to two writers at once, e.g. to save the output and hash it in one pass.
+pub fn decompress_tee<R: BufRead, W1: Write, W2: Write>(
+    input: R,
+    primary: W1,
+    secondary: W2,
+) -> Result<u64> {
+    decompress(input, TeeWriter::new(primary, secondary))
+}
+
+/// Decompresses with `transform` applied to the output before it reaches `output` (e.g. to
+/// filter or re-encode it on the fly). The transform is applied after `TrackingWriter`, so the
+/// back-reference history and CRC32 checked against the footers are of the decompressed
+/// bytes as they are. Returns the number of decompressed bytes before the transform.
+pub fn decompress_with_transform<R: BufRead, W: Write>(
+    input: R,
+    output: W,
+    transform: impl FnMut(&[u8]) -> Vec<u8>,
+) -> Result<u64> {
+    decompress(input, TransformWriter::new(output, transform))
+}
+
+/// Decompresses from a plain `Read` source (e.g. a socket), buffering it with at least
+/// `INPUT_BUFFER_SIZE` bytes, so that the bit reader doesn't issue a read per byte.
+pub fn decompress_reader<R: Read, W: Write>(input: R, output: W) -> Result<u64> {
+    decompress(BufReader::with_capacity(INPUT_BUFFER_SIZE, input), output)
+}
+
+/// Decompresses gzip data held in memory. Same as `decompress(input, output)`, since
+/// `&[u8]` already implements `BufRead`, and no `Cursor` is needed for it.
+pub fn decompress_slice<W: Write>(input: &[u8], output: W) -> Result<u64> {
+    decompress(input, output)
+}
+
+/// Appends the decompressed data to `output`, reusing its capacity, e.g. to reassemble chunked
+/// data in one buffer. Back-references reach only the output of this call, not the bytes that
+/// were in `output` before. On failure `output` is truncated back to its original length.
+/// Returns the number of appended bytes.
+pub fn decompress_append<R: BufRead>(input: R, output: &mut Vec<u8>) -> Result<u64> {
+    let original_len = output.len();
+
+    let result = decompress(input, &mut *output);
+    if result.is_err() {
+        output.truncate(original_len);
+    }
+    result
+}
+
+/// Checks only that ISIZE of every member matches its decompressed size, returning `false`
+/// on the first mismatch. Data is still decoded, but discarded and without computing CRC32,
+/// so this is faster than a full verification. Other corruption is an error.
+pub fn check_length_only<R: BufRead>(input: R) -> Result<bool> {
+    let options = DecompressOptions {
+        skip_crc32: true,
+        ..Default::default()
+    };
+
+    match decompress_with_options(input, NullWriter::new(), &options) {
+        Ok(_) => Ok(true),
+        Err(error) => match error.downcast_ref() {
+            Some(DecompressError::LengthMismatch { .. }) => Ok(false),
+            _ => Err(error),
+        },
+    }
+}
+
+/// Compression type of the first deflate block, found by reading only the gzip header and the
+/// 3 bits of the block header, without decoding anything (e.g. to pass stored data through
+/// cheaply). The input is consumed past the block header. `Reserved` is returned as is.
+pub fn peek_first_block_type<R: BufRead>(input: R) -> Result<CompressionType> {
+    let mut gzip_reader = GzipReader::new(input, TrackingWriter::new(NullWriter::new()));
+    let header = gzip_reader.next_header()?;
+    if let CompressionMethod::Unknown(method) = header.compression_method {
+        bail!(DecompressError::UnsupportedCompressionMethod(method));
+    }
+
+    let mut deflate_reader = gzip_reader.into_deflate_reader();
+    Ok(deflate_reader.read_header()?.compression_type)
+}
+
+/// CRC32 of the whole decompressed output (of all members together), which is discarded. For
+/// comparing with an externally known checksum; footers are still checked as usual.
+pub fn decompressed_crc32<R: BufRead>(input: R) -> Result<u32> {
+    let mut output = Crc32Writer::new(NullWriter::new());
+    decompress(input, &mut output)?;
+    Ok(output.crc32())
+}
 
-        match gzip_reader.next_member() {
-            Ok((header, deflate_reader)) => {
-                trace!("Gzip member header: {:?}", header);
+/// Decompresses while checking externally known CRC32 and size of the output (e.g. from
+/// a manifest) besides the footers. For a single member the expected values are compared
+/// with its footer first, failing with `ExpectedFooterMismatch`; then with the whole output,
+/// failing with `ExpectedOutputMismatch`.
+pub fn decompress_verify_against<R: BufRead, W: Write>(
+    input: R,
+    output: W,
+    expected_crc32: u32,
+    expected_size: u64,
+) -> Result<DecompressStats> {
+    let mut output = Crc32Writer::new(output);
+    let stats = decompress_with_options(input, &mut output, &DecompressOptions::default())?;
 
-                // gzip_reader may be reused in case of multiple compressed files in one gzip
-                gzip_reader = process_compressed_data(deflate_reader)?;
+    if let [member] = stats.members.as_slice() {
+        let footer = &member.footer;
+        ensure!(
+            footer.data_crc32 == expected_crc32 && footer.data_size == expected_size as u32,
+            DecompressError::ExpectedFooterMismatch {
+                expected_crc32,
+                expected_size,
+                footer_crc32: footer.data_crc32,
+                footer_size: footer.data_size,
+            }
+        );
+    }
+
+    ensure!(
+        output.crc32() == expected_crc32 && output.byte_count() == expected_size,
+        DecompressError::ExpectedOutputMismatch {
+            expected_crc32,
+            expected_size,
+            actual_crc32: output.crc32(),
+            actual_size: output.byte_count(),
+        }
+    );
+
+    Ok(stats)
+}
+
+/// Decompresses input that must have exactly one member (like in HTTP or PNG), failing with
+/// `DecompressError::TooManyMembers` as soon as the header of a second one shows up, as silently
+/// accepting concatenated members may hide bugs or smuggled data. Returns the member's header.
+pub fn ensure_single_member<R: BufRead, W: Write>(input: R, output: W) -> Result<MemberHeader> {
+    let options = DecompressOptions {
+        max_members: Some(1),
+        ..Default::default()
+    };
+
+    let mut stats = decompress_with_options(input, output, &options)?;
+    let member = stats.members.pop().context("Input has no gzip members!")?;
+    Ok(member.header)
+}
+
+/// Decompresses to stdout through a `BufWriter`, flushing it after every member, so that
+/// the consumers of a pipe (like `zcat | grep`) get the output promptly.
+pub fn decompress_to_stdout<R: BufRead>(input: R) -> Result<DecompressStats> {
+    let mut output = BufWriter::new(io::stdout().lock());
+    let options = DecompressOptions {
+        flush_each_member: true,
+        ..Default::default()
+    };
+
+    decompress_with_options(input, &mut output, &options)
+}
+
+pub fn decompress_with_options<R: BufRead, W: Write>(
+    input: R,
+    output: W,
+    options: &DecompressOptions,
+) -> Result<DecompressStats> {
+    decompress_buffered(input, output, options, &mut VecDeque::new(), None)
+}
+
+/// Decompresses `input` like `decompress_with_options`, passing every decoded LZ77 token
+/// to `on_token` in stream order.
+pub fn decompress_with_tokens<R: BufRead, W: Write>(
+    input: R,
+    output: W,
+    options: &DecompressOptions,
+    mut on_token: impl FnMut(Lz77Token),
+) -> Result<DecompressStats> {
+    decompress_buffered(
+        input,
+        output,
+        options,
+        &mut VecDeque::new(),
+        Some(&mut on_token),
+    )
+}
+
+// `history` is the allocation for the back-reference history, given back after success
+// for the next call (see `Decompressor`)
+fn decompress_buffered<R: BufRead, W: Write>(
+    input: R,
+    output: W,
+    options: &DecompressOptions,
+    history: &mut VecDeque<u8>,
+    on_token: Option<&mut dyn FnMut(Lz77Token)>,
+) -> Result<DecompressStats> {
+    match options.output_buffer_size {
+        Some(capacity) => decompress_to(
+            input,
+            BufWriter::with_capacity(capacity, output),
+            options,
+            history,
+            on_token,
+        ),
+        None => decompress_to(input, output, options, history, on_token),
+    }
+}
+
+fn decompress_to<R: BufRead, W: Write>(
+    input: R,
+    mut output: W,
+    options: &DecompressOptions,
+    history: &mut VecDeque<u8>,
+    on_token: Option<&mut dyn FnMut(Lz77Token)>,
+) -> Result<DecompressStats> {
+    // the output has parts of broken members then, so its CRC32 wouldn't be of intact data
+    ensure!(
+        !(options.skip_corrupt_members && options.whole_output_crc32),
+        "whole_output_crc32 can't be combined with skip_corrupt_members!"
+    );
 
-                info!("Member decompression finished successfully!");
+    let stats = if options.whole_output_crc32 {
+        // of the output as it is written, i.e. after newline conversion
+        let mut output = Crc32Writer::new(&mut output);
+        let mut stats = decompress_or_skip(input, &mut output, options, history, on_token)?;
+        if !stats.truncated_final_member {
+            stats.output_crc32 = Some(output.crc32());
+        }
+        stats
+    } else {
+        decompress_or_skip(input, &mut output, options, history, on_token)?
+    };
+
+    // the writer of a truncated final member is lost before the flush after the last member,
+    // and a buffered output would report a write error only on drop, if at all
+    if stats.truncated_final_member {
+        output.flush().context("Failed to flush output!")?;
+    }
+    Ok(stats)
+}
+
+fn decompress_or_skip<R: BufRead, W: Write>(
+    input: R,
+    mut output: W,
+    options: &DecompressOptions,
+    history: &mut VecDeque<u8>,
+    mut on_token: Option<&mut dyn FnMut(Lz77Token)>,
+) -> Result<DecompressStats> {
+    if !options.skip_corrupt_members {
+        let writer =
+            TrackingWriter::with_history(NewlineNormalizer::new(output), mem::take(history));
+        let (stats, writer) = decompress_members(input, writer, options, on_token)?;
+        if let Some(writer) = writer {
+            *history = writer.into_history();
+        }
+        return Ok(stats);
+    }
+
+    // every failed member loses the reader and writer it was decoded with, so the member
+    // loop is restarted after it with new ones over the same input and output
+    let mut input = CountingReader::new(input);
+    let mut stats = DecompressStats::default();
+    loop {
+        let start_offset = input.position();
+        let decoded_members = stats.members.len();
+        let writer =
+            TrackingWriter::with_history(NewlineNormalizer::new(&mut output), mem::take(history));
+        let result = decompress_members_into(
+            &mut input,
+            writer,
+            options,
+            reborrow_on_token(&mut on_token),
+            &mut stats,
+        );
+
+        // offsets of the restarted loop are counted from its start
+        if stats.members.len() > decoded_members {
+            stats.end_offset += start_offset;
+        }
+        let error = match result {
+            Ok(writer) => {
+                if let Some(writer) = writer {
+                    *history = writer.into_history();
+                }
+                return Ok(stats);
             }
+            Err(error) if is_member_corruption(&error) => error,
+            Err(error) => return Err(error),
+        };
+
+        let offset = if stats.members.len() > decoded_members {
+            stats.end_offset
+        } else {
+            start_offset
+        };
+        warn!("Skipping corrupt member at offset {}: {:#}", offset, error);
+        skip_to_magic(&mut input).context("Failed to look for the next member!")?;
+        stats.skipped_members.push(SkippedMember {
+            offset,
+            resume_offset: input.position(),
+            error,
+        });
+    }
+}
 
+// whether the error is caused by the data of a member, so that the following ones
+// may still be intact, rather than by the input or output themselves or by a limit
+fn is_member_corruption(error: &anyhow::Error) -> bool {
+    error.chain().all(|cause| {
+        if let Some(error) = cause.downcast_ref::<io::Error>() {
+            return error.kind() == io::ErrorKind::UnexpectedEof;
+        }
+        !matches!(
+            cause.downcast_ref(),
+            Some(DecompressError::Timeout | DecompressError::TooManyMembers { .. })
+        )
+    })
+}
+
+// consumes the input up to the next gzip magic (or to its end), judging by the bytes available:
+// ID1 at the end of the input's buffer is taken for the start of the magic
+fn skip_to_magic<R: BufRead>(input: &mut R) -> io::Result<()> {
+    loop {
+        let buf = input.fill_buf()?;
+        if buf.is_empty() {
+            return Ok(());
+        }
+
+        let found = buf
+            .windows(2)
+            .position(|pair| pair == [ID1, ID2])
+            .or_else(|| (buf.last() == Some(&ID1)).then(|| buf.len() - 1));
+        match found {
+            Some(offset) => {
+                input.consume(offset);
+                return Ok(());
+            }
+            None => {
+                let len = buf.len();
+                input.consume(len);
+            }
+        }
+    }
+}
+
+// decompresses all members, giving back the writer for reuse of its buffers
+// (unless it was lost with a truncated member)
+fn decompress_members<R: BufRead, W: Write>(
+    input: R,
+    writer: TrackingWriter<NewlineNormalizer<W>>,
+    options: &DecompressOptions,
+    on_token: Option<&mut dyn FnMut(Lz77Token)>,
+) -> Result<(
+    DecompressStats,
+    Option<TrackingWriter<NewlineNormalizer<W>>>,
+)> {
+    let mut stats = DecompressStats::default();
+    let writer = decompress_members_into(input, writer, options, on_token, &mut stats)?;
+    Ok((stats, writer))
+}
+
+// the member loop, adding to `stats` (which are kept on failure too); `end_offset` is counted
+// from the start of `input`
+fn decompress_members_into<R: BufRead, W: Write>(
+    input: R,
+    mut writer: TrackingWriter<NewlineNormalizer<W>>,
+    options: &DecompressOptions,
+    mut on_token: Option<&mut dyn FnMut(Lz77Token)>,
+    stats: &mut DecompressStats,
+) -> Result<Option<TrackingWriter<NewlineNormalizer<W>>>> {
+    let mut context = Context::new(options, reborrow_on_token(&mut on_token));
+    context.block_types = stats.block_types;
+    if let Some(distances) = stats.distances.take() {
+        context.distances = Some(distances);
+    }
+
+    writer.set_lenient_distance(options.lenient_distance);
+    writer.set_crc_enabled(!options.skip_crc32);
+
+    let gzip_reader = GzipReader::new(CountingReader::new(input), writer);
+    let result = read_members(gzip_reader, &mut context, stats);
+
+    stats.block_types = context.block_types;
+    stats.distances = context.distances;
+    result
+}
+
+fn read_members<R: BufRead, W: Write>(
+    mut gzip_reader: GzipReader<CountingReader<R>, NewlineNormalizer<W>>,
+    context: &mut Context,
+    stats: &mut DecompressStats,
+) -> Result<Option<TrackingWriter<NewlineNormalizer<W>>>> {
+    let options = context.options;
+
+    info!("Decompression started!");
+    while !gzip_reader.is_empty()? {
+        info!("Starting to process member...");
+
+        // the reader is recreated after every member, so the setting is applied to each
+        gzip_reader.set_lenient_header_crc(options.ignore_header_crc16);
+        gzip_reader.set_lenient_extra_field(options.lenient_extra_field);
+        gzip_reader.set_max_string_len(options.max_string_len.unwrap_or(DEFAULT_MAX_STRING_LEN));
+        let member_start = gzip_reader.get_reader().position();
+        if !stats.members.is_empty() && !gzip_reader.at_magic()? {
+            error!("Non-gzip data after the last member!");
+            bail!(DecompressError::TrailingNonGzipData {
+                offset: member_start,
+                first_bytes: gzip_reader.peek(TRAILING_PEEK_LEN)?.to_vec(),
+            });
+        }
+        // after the check of trailing data, so that only another member counts against the limit
+        if let Some(limit) = options.max_members {
+            if stats.members.len() >= limit {
+                error!("Too many Gzip members!");
+                bail!(DecompressError::TooManyMembers { limit });
+            }
+        }
+        let header = match gzip_reader.next_header() {
+            Ok(header) => header,
             Err(error) => {
                 error!("Unable to read Gzip member header!");
                 return Err(error);
             }
+        };
+        trace!("Gzip member header: {:?}", header);
+
+        if let CompressionMethod::Unknown(method) = header.compression_method {
+            if options.pass_through_unknown_method {
+                warn!(
+                    "Passing the rest of input through as a member with unknown compression method: {}",
+                    method
+                );
+                pass_through_raw(gzip_reader.get_reader_mut(), &header, context)?;
+                stats.stopped_at_unknown_method = true;
+                break;
+            }
+
+            if options.stop_at_unknown_method && !stats.members.is_empty() {
+                warn!(
+                    "Stopping at member with unknown compression method: {}",
+                    method
+                );
+                stats.stopped_at_unknown_method = true;
+                break;
+            }
+
+            error!("Unable to decompress Gzip member!");
+            bail!(DecompressError::UnsupportedCompressionMethod(method));
+        }
+
+        if let Some(observer) = context.observer() {
+            observer.on_member_start(&header);
         }
+
+        let deflate_start = gzip_reader.get_reader().position();
+        let mut deflate_reader = gzip_reader.into_deflate_reader();
+        deflate_reader.set_input_offset(deflate_start);
+        deflate_reader
+            .set_recording_input(options.observe_compressed_data && options.observer.is_some());
+        deflate_reader
+            .writer_mut()
+            .inner_mut()
+            .set_line_ending(match options.text_mode {
+                TextMode::ConvertNewlines(line_ending) if header.is_text => Some(line_ending),
+                _ => None,
+            });
+
+        // gzip_reader may be reused in case of multiple compressed files in one gzip
+        let (footer, next_gzip_reader) = match process_compressed_data(deflate_reader, context) {
+            Ok(result) => result,
+            Err(error) if options.allow_truncated_final_member && is_truncation(&error) => {
+                warn!("Input ended in the middle of the last member: {:#}", error);
+                stats.truncated_final_member = true;
+                return Ok(None);
+            }
+            Err(error) => return Err(error),
+        };
+        gzip_reader = next_gzip_reader;
+
+        if options.flush_each_member {
+            gzip_reader
+                .flush_writer()
+                .context("Failed to flush output!")?;
+        }
+
+        stats.end_offset = gzip_reader.get_reader().position();
+        stats.members.push(MemberInfo {
+            header,
+            footer,
+            compressed_size: stats.end_offset - member_start,
+            output_size: context.member_output_size,
+            computed_crc32: context.member_crc32,
+            padding_bits: context.member_padding,
+            block_trees: std::mem::take(&mut context.block_trees),
+        });
+
+        info!("Member decompression finished successfully!");
     }
 
-    info!("All Gzip members decompressed successfully!");
+    // buffered writers would otherwise report a write error only on drop, if at all
+    gzip_reader
+        .flush_writer()
+        .context("Failed to flush output!")?;
 
-    Ok(())
+    info!("All Gzip members decompressed successfully!");
+    Ok(Some(gzip_reader.into_writer()))
 }
@@ -0,0 +1,99 @@
+#![forbid(unsafe_code)]
+
+use std::io::BufRead;
+
+use anyhow::{Context, Result};
+
+use crate::chunks::decompress_chunks;
+
+////////////////////////////////////////////////////////////////////////////////
+
+// splits the chunks of output into lines like BufRead::lines() does
+struct Lines<I> {
+    chunks: I,
+    buf: Vec<u8>,
+    // start of the first line not yielded yet
+    start: usize,
+    // end of the part after `start` already searched for a newline, so that a line longer
+    // than a chunk isn't searched again with every chunk
+    scanned: usize,
+    is_finished: bool,
+}
+
+impl<I: Iterator<Item = Result<Vec<u8>>>> Lines<I> {
+    // `end` is the position of the newline, or the end of the buffer for the last line,
+    // which keeps a trailing CR like BufRead::lines() does
+    fn take_line(&mut self, end: usize) -> Result<String> {
+        let mut line = &self.buf[self.start..end];
+        if end < self.buf.len() && line.last() == Some(&b'\r') {
+            line = &line[..line.len() - 1];
+        }
+
+        let line = String::from_utf8(line.to_vec()).context("Line is not valid UTF-8!");
+        self.start = (end + 1).min(self.buf.len());
+        self.scanned = self.start;
+        line
+    }
+}
+
+impl<I: Iterator<Item = Result<Vec<u8>>>> Iterator for Lines<I> {
+    type Item = Result<String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(len) = self.buf[self.scanned..]
+                .iter()
+                .position(|&byte| byte == b'\n')
+            {
+                return Some(self.take_line(self.scanned + len));
+            }
+            self.scanned = self.buf.len();
+            if self.is_finished {
+                return None;
+            }
+
+            match self.chunks.next() {
+                Some(Ok(chunk)) => {
+                    // only the lines taken since the previous chunk are dropped, so every byte
+                    // is moved at most once
+                    if self.start > 0 {
+                        self.buf.drain(..self.start);
+                        self.scanned -= self.start;
+                        self.start = 0;
+                    }
+                    self.buf.extend(chunk);
+                }
+                Some(Err(error)) => {
+                    self.is_finished = true;
+                    self.buf.clear();
+                    self.start = 0;
+                    self.scanned = 0;
+                    return Some(Err(error));
+                }
+                // the last line may have no newline at the end
+                None => {
+                    self.is_finished = true;
+                    if self.start < self.buf.len() {
+                        return Some(self.take_line(self.buf.len()));
+                    }
+                }
+            }
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Iterates over the lines of decompressed text, treating all members as one continuous
+/// stream. Built on `decompress_chunks`, so decompression runs on a separate thread, a few
+/// chunks ahead of the iterator. A decompression error is yielded as the last item, after the
+/// lines completed before it.
+pub fn gzip_lines<R: BufRead + Send + 'static>(input: R) -> impl Iterator<Item = Result<String>> {
+    Lines {
+        chunks: decompress_chunks(input),
+        buf: Vec::new(),
+        start: 0,
+        scanned: 0,
+        is_finished: false,
+    }
+}
@@ -1,11 +1,11 @@
 #![forbid(unsafe_code)]
 
-use std::io::{stdin, stdout};
+use std::io::stdin;
 
 use log::*;
 use structopt::StructOpt;
 
-use ripgzip::decompress;
+use ripgzip::decompress_to_stdout;
 
 #[derive(StructOpt, Debug)]
 #[structopt()]
@@ -27,7 +27,7 @@ fn main() {
         .expect("failed to initialize logging");
 
     if opts.decompress {
-        if let Err(err) = decompress(stdin().lock(), stdout().lock()) {
+        if let Err(err) = decompress_to_stdout(stdin().lock()) {
             error!("{:#}", err);
             std::process::exit(1);
         }
@@ -0,0 +1,78 @@
+#![forbid(unsafe_code)]
+
+use std::io::{BufRead, Write};
+
+use anyhow::{bail, Context as _, Result};
+
+use crate::{
+    counting_reader::CountingReader, gzip::GzipReader, process_compressed_data,
+    tracking_writer::TrackingWriter, CompressionMethod, Context, DecompressError,
+    DecompressOptions, MemberHeader,
+};
+
+////////////////////////////////////////////////////////////////////////////////
+
+const UNUSABLE: &str = "Member reader is unusable after an error!";
+
+/// Reads gzip members one by one with the default options, for the functions handling every
+/// member on its own (splitting, extracting, rewriting headers). Between the header and the
+/// data of a member the caller may switch the output or act on the input.
+pub(crate) struct MemberReader<R, W> {
+    // lost if decoding a member fails
+    gzip_reader: Option<GzipReader<CountingReader<R>, W>>,
+    options: DecompressOptions,
+}
+
+impl<R: BufRead, W: Write> MemberReader<R, W> {
+    pub fn new(input: R, output: W) -> Self {
+        Self {
+            gzip_reader: Some(GzipReader::new(
+                CountingReader::new(input),
+                TrackingWriter::new(output),
+            )),
+            options: DecompressOptions::default(),
+        }
+    }
+
+    /// Header of the next member, `None` at the end of input. A member with an unknown
+    /// compression method fails with `DecompressError::UnsupportedCompressionMethod`.
+    pub fn next_header(&mut self) -> Result<Option<MemberHeader>> {
+        let gzip_reader = self.gzip_reader()?;
+        if gzip_reader.is_empty()? {
+            return Ok(None);
+        }
+
+        let header = gzip_reader.next_header()?;
+        if let CompressionMethod::Unknown(method) = header.compression_method {
+            bail!(DecompressError::UnsupportedCompressionMethod(method));
+        }
+        Ok(Some(header))
+    }
+
+    /// Decodes the data and checks the footer of the member whose header was read last.
+    /// Returns the number of decompressed bytes.
+    pub fn read_data(&mut self) -> Result<u64> {
+        let gzip_reader = self.gzip_reader.take().context(UNUSABLE)?;
+        let mut context = Context::new(&self.options, None);
+
+        let deflate_start = gzip_reader.get_reader().position();
+        let mut deflate_reader = gzip_reader.into_deflate_reader();
+        deflate_reader.set_input_offset(deflate_start);
+
+        let (_, gzip_reader) = process_compressed_data(deflate_reader, &mut context)?;
+        self.gzip_reader = Some(gzip_reader);
+        Ok(context.member_output_size)
+    }
+
+    pub fn input_mut(&mut self) -> Result<&mut R> {
+        Ok(self.gzip_reader()?.get_reader_mut().get_mut())
+    }
+
+    pub fn output_mut(&mut self) -> Result<&mut W> {
+        Ok(self.gzip_reader()?.writer_mut().inner_mut())
+    }
+
+    fn gzip_reader(&mut self) -> Result<&mut GzipReader<CountingReader<R>, W>> {
+        self.gzip_reader.as_mut().context(UNUSABLE)
+    }
+}
@@ -0,0 +1,124 @@
+#![forbid(unsafe_code)]
+
+use std::io::{self, Write};
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Line ending written in place of CRLF, CR and LF by `TextMode::ConvertNewlines`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LineEnding {
+    Lf,
+    CrLf,
+    /// CRLF on Windows, LF elsewhere
+    Host,
+}
+
+impl LineEnding {
+    pub fn as_bytes(&self) -> &'static [u8] {
+        match self {
+            Self::Lf => b"\n",
+            Self::CrLf => b"\r\n",
+            Self::Host if cfg!(windows) => b"\r\n",
+            Self::Host => b"\n",
+        }
+    }
+}
+
+/// Writer converting CRLF, CR and LF line endings to the given one while enabled.
+pub struct NewlineNormalizer<T> {
+    inner: T,
+    line_ending: Option<LineEnding>,
+    after_cr: bool,
+}
+
+impl<T: Write> Write for NewlineNormalizer<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let Some(line_ending) = self.line_ending else {
+            return self.inner.write(buf);
+        };
+        let line_ending = line_ending.as_bytes();
+
+        let mut converted = Vec::with_capacity(buf.len());
+        for &byte in buf {
+            match byte {
+                b'\n' if self.after_cr => {}
+                b'\r' | b'\n' => converted.extend_from_slice(line_ending),
+                _ => converted.push(byte),
+            }
+
+            self.after_cr = byte == b'\r';
+        }
+
+        // the whole buffer is consumed, or nothing at all on error
+        self.inner.write_all(&converted)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<T: Write> NewlineNormalizer<T> {
+    pub fn new(inner: T) -> Self {
+        Self {
+            inner,
+            line_ending: None,
+            after_cr: false,
+        }
+    }
+
+    /// `None` disables the conversion.
+    pub fn set_line_ending(&mut self, line_ending: Option<LineEnding>) {
+        self.line_ending = line_ending;
+        self.after_cr = false;
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize() -> io::Result<()> {
+        let mut buf = Vec::new();
+        let mut writer = NewlineNormalizer::new(&mut buf);
+        writer.set_line_ending(Some(LineEnding::Lf));
+
+        writer.write_all(b"a\r\nb\rc\r")?;
+        writer.write_all(b"\nd\n\n")?;
+        assert_eq!(buf, b"a\nb\nc\nd\n\n");
+
+        Ok(())
+    }
+
+    #[test]
+    fn to_crlf() -> io::Result<()> {
+        let mut buf = Vec::new();
+        let mut writer = NewlineNormalizer::new(&mut buf);
+        writer.set_line_ending(Some(LineEnding::CrLf));
+
+        writer.write_all(b"a\nb\r")?;
+        writer.write_all(b"\nc\rd\r\n")?;
+        assert_eq!(buf, b"a\r\nb\r\nc\r\nd\r\n");
+
+        Ok(())
+    }
+
+    #[test]
+    fn disabled() -> io::Result<()> {
+        let mut buf = Vec::new();
+        let mut writer = NewlineNormalizer::new(&mut buf);
+
+        writer.write_all(b"a\r\nb\r")?;
+        assert_eq!(buf, b"a\r\nb\r");
+
+        Ok(())
+    }
+}
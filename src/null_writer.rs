@@ -0,0 +1,51 @@
+#![forbid(unsafe_code)]
+
+use std::io::{self, Write};
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Writer discarding everything written to it, but counting the bytes. Useful to verify
+/// or scan an archive without keeping its content.
+#[derive(Debug, Default)]
+pub struct NullWriter {
+    byte_count: u64,
+}
+
+impl NullWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn byte_count(&self) -> u64 {
+        self.byte_count
+    }
+}
+
+impl Write for NullWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.byte_count += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn byte_count() -> io::Result<()> {
+        let mut writer = NullWriter::new();
+        assert_eq!(writer.write(&[1, 2, 3])?, 3);
+        writer.write_all(&[0; 1000])?;
+        writer.flush()?;
+
+        assert_eq!(writer.byte_count(), 1003);
+        Ok(())
+    }
+}
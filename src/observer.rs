@@ -0,0 +1,32 @@
+#![forbid(unsafe_code)]
+
+use crate::{
+    deflate::BlockHeader,
+    gzip::{MemberFooter, MemberHeader},
+};
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Hooks called while decompressing, e.g. for progress reporting or metrics.
+/// Like `log::Log`, hooks take `&self`, so stateful observers need interior mutability.
+pub trait Observer {
+    fn on_member_start(&self, _header: &MemberHeader) {}
+
+    /// Called before the content of each deflate block is read. The header carries the index
+    /// of the block within the member and whether it is the final one.
+    fn on_block(&self, _header: &BlockHeader) {}
+
+    fn on_member_end(&self, _footer: &MemberFooter) {}
+
+    /// Raw payload of a member with an unknown compression method, in chunks, when
+    /// `pass_through_unknown_method` is set.
+    fn on_raw_payload(&self, _header: &MemberHeader, _data: &[u8]) {}
+
+    /// Raw deflate data of a member as it is consumed, in pieces of one or more blocks (from
+    /// the end of the header up to the footer), when `observe_compressed_data` is set.
+    fn on_compressed_data(&self, _data: &[u8]) {}
+
+    /// Number of compressed bytes consumed and decompressed bytes produced since the
+    /// previous call. Called after every block and member footer.
+    fn on_bytes(&self, _input: u64, _output: u64) {}
+}
@@ -0,0 +1,86 @@
+#![forbid(unsafe_code)]
+
+use std::time::Instant;
+
+use crate::{newline_writer::LineEnding, observer::Observer};
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Handling of the output of members flagged as text.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TextMode {
+    #[default]
+    Raw,
+    /// Convert CRLF, CR and LF line endings to the given one. Applied after CRC32 and history,
+    /// which are of the decompressed bytes as they are
+    ConvertNewlines(LineEnding),
+}
+
+#[derive(Default)]
+pub struct DecompressOptions {
+    /// Treat back-references pointing before the start of output as referencing zeros
+    /// (like some lenient decoders do) instead of failing
+    pub lenient_distance: bool,
+    /// Don't compute CRC32 of the output at all (so it isn't verified), for speed
+    pub skip_crc32: bool,
+    /// Only warn when the header CRC16 doesn't match (some broken writers compute it
+    /// incorrectly) instead of failing with `DecompressError::HeaderCrcMismatch`
+    pub ignore_header_crc16: bool,
+    /// Only warn when ISIZE doesn't match the decompressed size but CRC32 does (a bug of some
+    /// writers) instead of failing with `DecompressError::LengthMismatch`. Has no effect with
+    /// `skip_crc32`, as nothing confirms the data then
+    pub lenient_length: bool,
+    /// Keep an extra field cut short by the end of input with a warning instead of failing
+    /// with `DecompressError::InvalidExtraField`
+    pub lenient_extra_field: bool,
+    /// Limit on the length of the name and comment fields, `DEFAULT_MAX_STRING_LEN` if not set.
+    /// Longer fields fail with `DecompressError::StringTooLong`
+    pub max_string_len: Option<u64>,
+    /// Fail with `DecompressError::TooManyMembers` if the input has more members than this,
+    /// bounding the work spent on streams of millions of tiny members. Only data starting with
+    /// the gzip magic counts as a member
+    pub max_members: Option<usize>,
+    /// How the output of members flagged as text (FTEXT) is written, as is by default
+    pub text_mode: TextMode,
+    /// Stop without an error when a member after the first one has an unknown compression
+    /// method, treating the rest of the input as unrelated trailing data
+    pub stop_at_unknown_method: bool,
+    /// Instead of failing at a member with an unknown compression method, pass the rest of the
+    /// input to `Observer::on_raw_payload`. Its length is unknown, so it only works when the
+    /// member is the last one
+    pub pass_through_unknown_method: bool,
+    /// Pass the raw deflate data of every member to `Observer::on_compressed_data`, e.g. to
+    /// keep the compressed blob alongside the decoded output without a second pass
+    pub observe_compressed_data: bool,
+    /// Stop without an error when the input ends in the middle of a member (e.g. a log file
+    /// still being written). Corrupted data is still an error
+    pub allow_truncated_final_member: bool,
+    /// Instead of failing at a corrupt member, skip to the next gzip magic after the point
+    /// where decoding failed and go on from there, listing the member in
+    /// `DecompressStats::skipped_members`. Output decoded from the corrupt member before the
+    /// failure stays written. Members starting inside the part consumed while decoding it are
+    /// lost, as the input can't be rewound
+    pub skip_corrupt_members: bool,
+    /// Compute CRC32 of the whole output (of all members together) into
+    /// `DecompressStats::output_crc32`, besides the per-member ones checked against the footers.
+    /// It's of the output as written, i.e. after newline conversion of `text_mode`.
+    /// Decompression fails right away if `skip_corrupt_members` is set as well
+    pub whole_output_crc32: bool,
+    /// Flush the output after every member, so that data reaches buffered writers' consumers
+    /// without waiting for the end of input
+    pub flush_each_member: bool,
+    /// Collect the output in a buffer of this size before passing it to the output writer, so
+    /// that it sees a few large writes instead of one per literal or match. The buffer is
+    /// flushed (together with the output writer) after every deflate block
+    pub output_buffer_size: Option<usize>,
+    /// Collect a histogram of back-reference distances into the stats, e.g. to analyze the
+    /// behavior of the encoder
+    pub collect_distances: bool,
+    /// Collect the Huffman code tables of every dynamic block into the member stats
+    pub collect_block_trees: bool,
+    /// Fail with `DecompressError::Timeout` once this moment has passed. Checked before every
+    /// deflate block, so a single huge block may overshoot it
+    pub deadline: Option<Instant>,
+    /// Hooks called at member and block boundaries
+    pub observer: Option<Box<dyn Observer>>,
+}
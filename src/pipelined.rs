@@ -0,0 +1,132 @@
+#![forbid(unsafe_code)]
+
+use std::{
+    io::{self, BufRead, Write},
+    mem,
+    sync::mpsc::{self, Receiver, SyncSender},
+    thread,
+};
+
+use anyhow::{anyhow, Context, Result};
+
+use crate::{decompress_with_options, DecompressOptions, DecompressStats};
+
+////////////////////////////////////////////////////////////////////////////////
+
+const CHUNK_SIZE: usize = 1 << 16;
+// chunks in flight between the threads, bounds the memory used by the pipeline
+const QUEUE_LEN: usize = 4;
+
+// sends the output to the writing thread in chunks of CHUNK_SIZE
+struct ChannelWriter {
+    sender: SyncSender<Vec<u8>>,
+    buffer: Vec<u8>,
+}
+
+impl ChannelWriter {
+    fn send_buffer(&mut self) -> io::Result<()> {
+        let chunk = mem::replace(&mut self.buffer, Vec::with_capacity(CHUNK_SIZE));
+        self.sender
+            .send(chunk)
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "output thread has stopped"))
+    }
+}
+
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = buf.len().min(CHUNK_SIZE - self.buffer.len());
+        self.buffer.extend_from_slice(&buf[..len]);
+
+        if self.buffer.len() == CHUNK_SIZE {
+            self.send_buffer()?;
+        }
+
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        self.send_buffer()
+    }
+}
+
+fn write_chunks<W: Write>(receiver: Receiver<Vec<u8>>, mut output: W) -> io::Result<()> {
+    for chunk in receiver {
+        output.write_all(&chunk)?;
+    }
+    output.flush()
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Experimental: same as `decompress_with_options`, but the output is written on a separate
+/// thread, so decoding of the next blocks overlaps with writing of the previous ones.
+///
+/// Blocks themselves are still decoded one after another: back-references cross block
+/// boundaries, and the position of the next block header is only known once the previous
+/// block is decoded.
+pub fn decompress_pipelined<R: BufRead, W: Write + Send>(
+    input: R,
+    output: W,
+    options: &DecompressOptions,
+) -> Result<DecompressStats> {
+    let (sender, receiver) = mpsc::sync_channel(QUEUE_LEN);
+
+    thread::scope(|scope| {
+        let output_thread = scope.spawn(move || write_chunks(receiver, output));
+
+        let mut writer = ChannelWriter {
+            sender,
+            buffer: Vec::with_capacity(CHUNK_SIZE),
+        };
+        // the last chunk is sent by the flush at the end of decompression
+        let result = decompress_with_options(input, &mut writer, options);
+        // closing the channel lets the output thread finish
+        drop(writer);
+
+        let output_result = output_thread
+            .join()
+            .map_err(|_| anyhow!("output thread panicked"))?
+            .context("Failed to write the output!");
+
+        // an output error is the cause of a decoding failure with a closed channel
+        output_result?;
+        result
+    })
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decompress;
+
+    #[test]
+    fn same_output() -> Result<()> {
+        let input = include_bytes!("../data/ok/06-war-and-peace.txt.gz").as_slice();
+
+        let mut expected = Vec::new();
+        decompress(input, &mut expected)?;
+
+        let mut output = Vec::new();
+        let stats = decompress_pipelined(input, &mut output, &DecompressOptions::default())?;
+
+        assert_eq!(output, expected);
+        assert_eq!(stats.output_size(), expected.len() as u64);
+        Ok(())
+    }
+
+    #[test]
+    fn output_error() {
+        let input = include_bytes!("../data/ok/06-war-and-peace.txt.gz").as_slice();
+        let mut output = [0u8; 100];
+
+        let error =
+            decompress_pipelined(input, output.as_mut_slice(), &DecompressOptions::default())
+                .unwrap_err();
+        assert!(format!("{:#}", error).contains("Failed to write the output"));
+    }
+}
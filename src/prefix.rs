@@ -0,0 +1,78 @@
+#![forbid(unsafe_code)]
+
+use std::{
+    error::Error,
+    fmt,
+    io::{self, BufRead, Write},
+};
+
+use anyhow::Result;
+
+use crate::decompress;
+
+////////////////////////////////////////////////////////////////////////////////
+
+// returned by LimitWriter once the limit is reached, to stop decoding early
+#[derive(Debug)]
+struct LimitReached;
+
+impl fmt::Display for LimitReached {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("output limit reached")
+    }
+}
+
+impl Error for LimitReached {}
+
+// passes through at most `remaining` bytes, failing with LimitReached afterwards
+struct LimitWriter<W> {
+    inner: W,
+    remaining: u64,
+}
+
+impl<W: Write> Write for LimitWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.remaining == 0 {
+            return Err(io::Error::other(LimitReached));
+        }
+
+        let len = buf
+            .len()
+            .min(self.remaining.try_into().unwrap_or(usize::MAX));
+        let written = self.inner.write(&buf[..len])?;
+        self.remaining -= written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+fn is_limit_reached(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| {
+        cause
+            .downcast_ref::<io::Error>()
+            .and_then(|error| error.get_ref())
+            .is_some_and(|error| error.is::<LimitReached>())
+    })
+}
+
+/// Decompresses at most `max_bytes` of output and stops without decoding the rest, e.g. to
+/// preview a large log. Integrity of the incomplete member isn't checked. Returns the number
+/// of bytes written, less than `max_bytes` if the whole input is shorter.
+pub fn decompress_prefix<R: BufRead, W: Write>(input: R, output: W, max_bytes: u64) -> Result<u64> {
+    let mut output = LimitWriter {
+        inner: output,
+        remaining: max_bytes,
+    };
+
+    match decompress(input, &mut output) {
+        Ok(size) => Ok(size),
+        Err(error) if is_limit_reached(&error) => {
+            output.flush()?;
+            Ok(max_bytes)
+        }
+        Err(error) => Err(error),
+    }
+}
@@ -0,0 +1,67 @@
+#![forbid(unsafe_code)]
+
+use std::{cell::RefCell, io::BufRead, io::Write, rc::Rc};
+
+use anyhow::{Context, Result};
+use flate2::{write::DeflateEncoder, Compression};
+
+use crate::{decompress_with_options, DecompressOptions, MemberHeader, Observer};
+
+////////////////////////////////////////////////////////////////////////////////
+
+// levels of flate2 (and zlib), 0 giving stored blocks
+const LEVELS: std::ops::RangeInclusive<u32> = 0..=9;
+
+// raw deflate data of every member
+#[derive(Default)]
+struct DeflateCollector {
+    members: RefCell<Vec<Vec<u8>>>,
+}
+
+impl Observer for Rc<DeflateCollector> {
+    fn on_member_start(&self, _header: &MemberHeader) {
+        self.members.borrow_mut().push(Vec::new());
+    }
+
+    fn on_compressed_data(&self, data: &[u8]) {
+        if let Some(member) = self.members.borrow_mut().last_mut() {
+            member.extend_from_slice(data);
+        }
+    }
+}
+
+fn compress(data: &[u8], level: u32) -> Result<Vec<u8>> {
+    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::new(level));
+    encoder.write_all(data).context("Failed to recompress!")?;
+    encoder.finish().context("Failed to recompress!")
+}
+
+/// Whether recompressing the decompressed data reproduces the deflate data of every member
+/// byte for byte, so with the same sequence of blocks and their types. The crate has no encoder
+/// of its own, so flate2 is tried with every level; data of other compressors usually isn't
+/// reproduced. A self-consistency check of the encoder and decoder: a discrepancy in bit
+/// order or Huffman code assignment shows up as `false`.
+pub fn recompress_matches<R: BufRead>(input: R) -> Result<bool> {
+    let collector = Rc::new(DeflateCollector::default());
+    let options = DecompressOptions {
+        observe_compressed_data: true,
+        observer: Some(Box::new(collector.clone())),
+        ..Default::default()
+    };
+
+    let mut output = Vec::new();
+    let stats = decompress_with_options(input, &mut output, &options)?;
+
+    let mut data = output.as_slice();
+    for (member, deflate) in stats.members.iter().zip(collector.members.take()) {
+        let (member_data, rest) = data.split_at(member.output_size as usize);
+        data = rest;
+
+        let mut recompressed = LEVELS.map(|level| compress(member_data, level));
+        if !recompressed.any(|result| result.is_ok_and(|result| result == deflate)) {
+            return Ok(false);
+        }
+    }
+
+    Ok(true)
+}
@@ -0,0 +1,69 @@
+#![forbid(unsafe_code)]
+
+use std::io::{BufRead, Write};
+
+use anyhow::{Context, Result};
+
+use crate::{members::MemberReader, tee_reader::TeeReader, MemberHeader, NullWriter};
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Header fields set to the same value in every member by `rewrite_headers_with_options`,
+/// e.g. to get identical headers regardless of the producing platform.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RewriteOptions {
+    pub os: Option<u8>,
+    pub extra_flags: Option<u8>,
+}
+
+/// Copies gzip `input` to `output` member by member, letting `modify` change the metadata of
+/// every header (e.g. clear the name or zero the mtime for reproducible builds). Compressed
+/// data and footers are copied unchanged, but still decoded to verify them. CRC16 of a header
+/// is recomputed if `has_crc` is set.
+pub fn rewrite_headers<R: BufRead, W: Write>(
+    input: R,
+    output: W,
+    modify: impl FnMut(&mut MemberHeader),
+) -> Result<()> {
+    rewrite_headers_with_options(input, output, &RewriteOptions::default(), modify)
+}
+
+/// Same as `rewrite_headers`, with OS and XFL overridden as set in `rewrite_options` after
+/// `modify` is applied.
+pub fn rewrite_headers_with_options<R: BufRead, W: Write>(
+    input: R,
+    output: W,
+    rewrite_options: &RewriteOptions,
+    mut modify: impl FnMut(&mut MemberHeader),
+) -> Result<()> {
+    let mut members = MemberReader::new(TeeReader::new(input, output), NullWriter::new());
+
+    while let Some(mut header) = members.next_header()? {
+        modify(&mut header);
+        if let Some(os) = rewrite_options.os {
+            header.os = os;
+        }
+        if let Some(extra_flags) = rewrite_options.extra_flags {
+            header.extra_flags = extra_flags;
+        }
+
+        let tee = members.input_mut()?;
+        tee.writer_mut()
+            .write_all(&header.to_bytes())
+            .context("Failed to write header!")?;
+        tee.set_copying(true);
+
+        members.read_data()?;
+
+        let tee = members.input_mut()?;
+        tee.set_copying(false);
+        tee.take_error()
+            .context("Failed to copy compressed data!")?;
+    }
+
+    members
+        .input_mut()?
+        .writer_mut()
+        .flush()
+        .context("Failed to flush output!")
+}
@@ -0,0 +1,29 @@
+#![forbid(unsafe_code)]
+
+use std::io::BufRead;
+
+use anyhow::Result;
+
+use crate::{members::MemberReader, MemberHeader};
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Decompresses `input` member by member, passing each header with the decoded data of the
+/// member to `for_each` before moving on to the next one. Only one member's output is held
+/// in memory at a time, and its buffer is reused.
+pub fn split_members<R: BufRead>(
+    input: R,
+    mut for_each: impl FnMut(MemberHeader, &[u8]),
+) -> Result<()> {
+    let mut members = MemberReader::new(input, Vec::new());
+
+    while let Some(header) = members.next_header()? {
+        members.read_data()?;
+
+        let output = members.output_mut()?;
+        for_each(header, output);
+        output.clear();
+    }
+
+    Ok(())
+}
@@ -0,0 +1,225 @@
+#![forbid(unsafe_code)]
+
+use crate::{
+    bit_reader::BitSequence,
+    block_trees::BlockTrees,
+    deflate::CompressionType,
+    gzip::{MemberFlags, MemberFooter, MemberHeader},
+};
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Debug)]
+pub struct MemberInfo {
+    pub header: MemberHeader,
+    pub footer: MemberFooter,
+    /// Number of input bytes taken by the member, header and footer included
+    pub compressed_size: u64,
+    /// Number of decompressed bytes, unlike ISIZE not truncated to 32 bits
+    pub output_size: u64,
+    /// CRC32 computed over the decompressed data (the one stored in the footer is
+    /// `footer.data_crc32`), `None` if `skip_crc32` was set
+    pub computed_crc32: Option<u32>,
+    /// Bits between the end of the final block and the byte boundary before the footer
+    /// (0-7 of them), normally zeros
+    pub padding_bits: BitSequence,
+    /// Code tables of the dynamic blocks, if `collect_block_trees` was requested
+    pub block_trees: Vec<BlockTrees>,
+}
+
+impl MemberInfo {
+    /// Whether the member was flagged as text (FTEXT) by its producer.
+    pub fn is_text(&self) -> bool {
+        self.header.is_text
+    }
+
+    /// Which optional header fields (extra, name, comment, header CRC) the member had.
+    pub fn present_fields(&self) -> MemberFlags {
+        self.header.flags()
+    }
+
+    /// Decompressed size divided by compressed size.
+    pub fn compression_ratio(&self) -> f64 {
+        self.output_size as f64 / self.compressed_size as f64
+    }
+}
+
+/// Member passed over because of `skip_corrupt_members`.
+#[derive(Debug)]
+pub struct SkippedMember {
+    /// Input offset where the member starts
+    pub offset: u64,
+    /// Input offset where decoding went on, at the next gzip magic or at the end of input
+    pub resume_offset: u64,
+    /// What the member failed with
+    pub error: anyhow::Error,
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Number of blocks of one type and the decompressed bytes they produced.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BlockTypeStats {
+    pub blocks: u64,
+    pub output_size: u64,
+}
+
+/// Blocks of every type over the whole stream.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BlockTypeCounts {
+    pub stored: BlockTypeStats,
+    pub fixed: BlockTypeStats,
+    pub dynamic: BlockTypeStats,
+}
+
+impl BlockTypeCounts {
+    pub(crate) fn add(&mut self, compression_type: CompressionType, output_size: u64) {
+        let stats = match compression_type {
+            CompressionType::Uncompressed => &mut self.stored,
+            CompressionType::FixedTree => &mut self.fixed,
+            CompressionType::DynamicTree => &mut self.dynamic,
+            // blocks of the reserved type fail before producing anything
+            CompressionType::Reserved => return,
+        };
+        stats.blocks += 1;
+        stats.output_size += output_size;
+    }
+}
+
+const DISTANCE_CODES: usize = 30;
+const MAX_DISTANCE: usize = 32768;
+
+/// Back-reference distances of all matches. Memory is bounded regardless of the input:
+/// a counter per distance code and a bit per possible distance.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DistanceHistogram {
+    by_code: [u64; DISTANCE_CODES],
+    seen: Vec<u64>,
+    distinct: usize,
+}
+
+impl Default for DistanceHistogram {
+    fn default() -> Self {
+        Self {
+            by_code: [0; DISTANCE_CODES],
+            seen: vec![0; (MAX_DISTANCE + 1).div_ceil(64)],
+            distinct: 0,
+        }
+    }
+}
+
+impl DistanceHistogram {
+    pub(crate) fn add(&mut self, distance: u16) {
+        self.by_code[distance_code(distance)] += 1;
+
+        let (word, bit) = (distance as usize / 64, distance as usize % 64);
+        if self.seen[word] & (1 << bit) == 0 {
+            self.seen[word] |= 1 << bit;
+            self.distinct += 1;
+        }
+    }
+
+    /// Number of matches per distance code (0-29 of RFC 1951, each covering a range of
+    /// distances).
+    pub fn by_code(&self) -> &[u64; DISTANCE_CODES] {
+        &self.by_code
+    }
+
+    /// Number of matches.
+    pub fn matches(&self) -> u64 {
+        self.by_code.iter().sum()
+    }
+
+    /// Number of distinct distances used.
+    pub fn distinct(&self) -> usize {
+        self.distinct
+    }
+
+    pub fn contains(&self, distance: u16) -> bool {
+        let (word, bit) = (distance as usize / 64, distance as usize % 64);
+        self.seen
+            .get(word)
+            .is_some_and(|word| word & (1 << bit) != 0)
+    }
+}
+
+// inverse of the distance code table: codes 0-3 are distances 1-4, then every pair of codes
+// covers the next power of two
+fn distance_code(distance: u16) -> usize {
+    let offset = distance as usize - 1;
+    if offset < 4 {
+        return offset;
+    }
+
+    let extra_bits = offset.ilog2() as usize - 1;
+    2 * extra_bits + 2 + ((offset >> extra_bits) & 1)
+}
+
+#[derive(Debug, Default)]
+pub struct DecompressStats {
+    pub members: Vec<MemberInfo>,
+    /// Decompression stopped at a member with an unknown compression method
+    pub stopped_at_unknown_method: bool,
+    /// Input ended in the middle of the last member. Its decoded part has been written to the
+    /// output, but it isn't listed in `members`
+    pub truncated_final_member: bool,
+    /// Input offset right after the footer of the last member in `members`, where data
+    /// following the gzip stream (e.g. in a custom container) starts
+    pub end_offset: u64,
+    /// Blocks of every type over all members, including a truncated one
+    pub block_types: BlockTypeCounts,
+    /// Distances of all matches, if `collect_distances` is set
+    pub distances: Option<DistanceHistogram>,
+    /// Corrupt members passed over, if `skip_corrupt_members` is set
+    pub skipped_members: Vec<SkippedMember>,
+    /// CRC32 of the whole output, if `whole_output_crc32` is set. Not computed if the final
+    /// member was truncated
+    pub output_crc32: Option<u32>,
+}
+
+impl DecompressStats {
+    /// Total number of decompressed bytes over all members.
+    pub fn output_size(&self) -> u64 {
+        self.members.iter().map(|member| member.output_size).sum()
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn distance_codes() {
+        let expected = [
+            (1, 0),
+            (4, 3),
+            (5, 4),
+            (6, 4),
+            (7, 5),
+            (257, 16),
+            (384, 16),
+            (385, 17),
+            (24576, 28),
+            (24577, 29),
+            (32768, 29),
+        ];
+        for (distance, code) in expected {
+            assert_eq!(distance_code(distance), code, "distance {distance}");
+        }
+    }
+
+    #[test]
+    fn distance_histogram() {
+        let mut histogram = DistanceHistogram::default();
+        for distance in [3, 3, 32768, 100] {
+            histogram.add(distance);
+        }
+
+        assert_eq!(histogram.matches(), 4);
+        assert_eq!(histogram.distinct(), 3);
+        assert!(histogram.contains(32768) && !histogram.contains(4));
+        assert_eq!(histogram.by_code()[2], 2);
+    }
+}
@@ -0,0 +1,91 @@
+#![forbid(unsafe_code)]
+
+use std::io::{self, BufRead, Read, Write};
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Reader copying the consumed bytes to a writer while copying is enabled.
+pub struct TeeReader<R, W> {
+    inner: R,
+    copy_to: W,
+    is_copying: bool,
+    // consume() can't fail, so a write error is kept until it can be reported
+    error: Option<io::Error>,
+}
+
+impl<R, W: Write> TeeReader<R, W> {
+    pub fn new(inner: R, copy_to: W) -> Self {
+        Self {
+            inner,
+            copy_to,
+            is_copying: false,
+            error: None,
+        }
+    }
+
+    pub fn set_copying(&mut self, is_copying: bool) {
+        self.is_copying = is_copying;
+    }
+
+    pub fn writer_mut(&mut self) -> &mut W {
+        &mut self.copy_to
+    }
+
+    /// Reports the first error of copying, if any.
+    pub fn take_error(&mut self) -> io::Result<()> {
+        self.error.take().map_or(Ok(()), Err)
+    }
+}
+
+impl<R: BufRead, W: Write> Read for TeeReader<R, W> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let len = available.len().min(buf.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        self.consume(len);
+        Ok(len)
+    }
+}
+
+impl<R: BufRead, W: Write> BufRead for TeeReader<R, W> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        if self.is_copying && self.error.is_none() {
+            // fill_buf() returns the same bytes until they're consumed
+            if let Err(error) = self
+                .inner
+                .fill_buf()
+                .and_then(|buf| self.copy_to.write_all(&buf[..amt]))
+            {
+                self.error = Some(error);
+            }
+        }
+        self.inner.consume(amt);
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn copying() -> io::Result<()> {
+        let data: &[u8] = b"header|payload";
+        let mut copy = Vec::new();
+        let mut reader = TeeReader::new(data, &mut copy);
+
+        let mut buf = [0u8; 7];
+        reader.read_exact(&mut buf)?;
+        reader.set_copying(true);
+        reader.read_to_end(&mut Vec::new())?;
+        reader.take_error()?;
+
+        assert_eq!(copy, b"payload");
+        Ok(())
+    }
+}
@@ -0,0 +1,58 @@
+#![forbid(unsafe_code)]
+
+use std::io::{self, Write};
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Writer passing everything written to it to both `primary` and `secondary` (e.g. a file
+/// and a hasher).
+pub struct TeeWriter<A, B> {
+    primary: A,
+    secondary: B,
+}
+
+impl<A: Write, B: Write> TeeWriter<A, B> {
+    pub fn new(primary: A, secondary: B) -> Self {
+        Self { primary, secondary }
+    }
+
+    pub fn into_inner(self) -> (A, B) {
+        (self.primary, self.secondary)
+    }
+}
+
+impl<A: Write, B: Write> Write for TeeWriter<A, B> {
+    // the secondary writer gets exactly the bytes accepted by the primary one
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.primary.write(buf)?;
+        self.secondary.write_all(&buf[..written])?;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.primary.flush()?;
+        self.secondary.flush()
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn both_written() -> io::Result<()> {
+        let mut short = [0u8; 4];
+        let mut copy = Vec::new();
+        let mut writer = TeeWriter::new(short.as_mut_slice(), &mut copy);
+
+        assert_eq!(writer.write(&[1, 2, 3])?, 3);
+        assert_eq!(writer.write(&[4, 5, 6])?, 1);
+        writer.flush()?;
+
+        assert_eq!(short, [1, 2, 3, 4]);
+        assert_eq!(copy, [1, 2, 3, 4]);
+        Ok(())
+    }
+}
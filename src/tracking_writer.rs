@@ -1,24 +1,30 @@
 #![forbid(unsafe_code)]
 
 use std::{
-    cmp::min,
     collections::VecDeque,
     io::{self, Write},
+    iter,
 };
 
 use anyhow::{bail, Context, Result};
-use crc::{Crc, Digest};
+
+use crate::checksum::{Checksum, Crc32};
 
 ////////////////////////////////////////////////////////////////////////////////
 
 const HISTORY_SIZE: usize = 32768;
-static CRC_ALGORITHM: Crc<u32> = Crc::<u32>::new(&crc::CRC_32_ISO_HDLC);
+#[cfg(feature = "debug-history")]
+const DEBUG_TAIL_LEN: usize = 32;
+// longest deflate match, so a back-reference is usually copied in one piece
+const COPY_CHUNK_SIZE: usize = 258;
 
 pub struct TrackingWriter<T> {
     inner: T,
     history: VecDeque<u8>,
-    digest: Digest<'static, u32>,
-    byte_count: usize,
+    checksum: Box<dyn Checksum + Send>,
+    is_checksum_enabled: bool,
+    byte_count: u64,
+    lenient_distance: bool,
 }
 
 impl<T: Write> Write for TrackingWriter<T> {
@@ -30,8 +36,10 @@ impl<T: Write> Write for TrackingWriter<T> {
             self.history.drain(..(self.history.len() - HISTORY_SIZE));
         }
 
-        self.digest.update(&buf[..written]);
-        self.byte_count += written;
+        if self.is_checksum_enabled {
+            self.checksum.update(&buf[..written]);
+        }
+        self.byte_count += written as u64;
 
         Ok(written)
     }
@@ -43,43 +51,146 @@ impl<T: Write> Write for TrackingWriter<T> {
 
 impl<T: Write> TrackingWriter<T> {
     pub fn new(inner: T) -> Self {
+        Self::with_history(inner, VecDeque::with_capacity(HISTORY_SIZE))
+    }
+
+    /// Same as `new`, but reuses the allocation of a history buffer (its content is cleared).
+    pub fn with_history(inner: T, mut history: VecDeque<u8>) -> Self {
+        history.clear();
+        history.reserve(HISTORY_SIZE);
+
         Self {
             inner,
-            history: VecDeque::<u8>::with_capacity(HISTORY_SIZE),
-            digest: CRC_ALGORITHM.digest(),
+            history,
+            checksum: Box::new(Crc32::new()),
+            is_checksum_enabled: true,
             byte_count: 0,
+            lenient_distance: false,
+        }
+    }
+
+    /// Same as `new`, but computes `checksum` of the output instead of CRC32 (e.g. Adler-32
+    /// for zlib).
+    pub fn with_checksum(inner: T, checksum: impl Checksum + Send + 'static) -> Self {
+        Self {
+            checksum: Box::new(checksum),
+            ..Self::new(inner)
         }
     }
 
+    /// Gives back the history buffer, so that its allocation can be reused.
+    pub fn into_history(self) -> VecDeque<u8> {
+        self.history
+    }
+
+    /// Fills the history with the tail of `dictionary`, so that back-references can reach
+    /// into it. Nothing is written to the inner writer or counted.
+    pub fn preset_dictionary(&mut self, dictionary: &[u8]) {
+        let tail = &dictionary[dictionary.len().saturating_sub(HISTORY_SIZE)..];
+        self.history.clear();
+        self.history.extend(tail);
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+
+    /// In lenient mode `write_previous` treats bytes before the start of output as zeros
+    /// instead of failing.
+    pub fn set_lenient_distance(&mut self, lenient_distance: bool) {
+        self.lenient_distance = lenient_distance;
+    }
+
     /// Write a sequence of `len` bytes written `dist` bytes ago.
     pub fn write_previous(&mut self, dist: usize, len: usize) -> Result<()> {
-        if dist > self.history.len() || len == 0 || dist == 0 {
-            bail!("Wrong write_previous() arguments provided: dist={}, len={} (current buffer size={})", dist, len, self.history.len());
+        let out_of_history =
+            dist > self.history.len() && (!self.lenient_distance || dist > HISTORY_SIZE);
+        if out_of_history || len == 0 || dist == 0 {
+            #[cfg(feature = "debug-history")]
+            log::debug!("History ends with {:?}", self.history_tail(DEBUG_TAIL_LEN));
+            bail!("Wrong write_previous() arguments provided: dist={}, len={} (current buffer size={})", dist, len, self.history_len());
         }
 
-        let slice_start = self.history.len() - dist;
-        let slice_end = min(slice_start + len, self.history.len());
+        // a run of the last byte, very common for padding and whitespace; before the start
+        // of output (lenient mode) the byte is zero
+        if dist == 1 {
+            let byte = self.history.back().copied().unwrap_or(0);
+            let buf = [byte; COPY_CHUNK_SIZE];
+            let mut remaining = len;
+            while remaining > 0 {
+                let chunk_len = remaining.min(COPY_CHUNK_SIZE);
+                self.write_all(&buf[..chunk_len])
+                    .context("Unable to write all slice of history bytes!")?;
+                remaining -= chunk_len;
+            }
+
+            return Ok(());
+        }
+
+        // copying in pieces of at most `dist` bytes, every piece is already in history
+        // when the next one is read (the case of len > dist)
+        let mut buf = [0u8; COPY_CHUNK_SIZE];
+        let mut remaining = len;
+        while remaining > 0 {
+            let chunk_len = remaining.min(dist).min(COPY_CHUNK_SIZE);
+
+            let zeros = dist.saturating_sub(self.history.len());
+            let slice_start = self.history.len() + zeros - dist;
+            iter::repeat_n(0, zeros)
+                .chain(self.history.range(slice_start..).copied())
+                .zip(&mut buf[..chunk_len])
+                .for_each(|(byte, slot)| *slot = byte);
+
+            self.write_all(&buf[..chunk_len])
+                .context("Unable to write all slice of history bytes!")?;
+            remaining -= chunk_len;
+        }
+
+        Ok(())
+    }
+
+    /// Clear the history, checksum and byte counter, keeping the same inner writer.
+    pub fn reset(&mut self) {
+        self.history.clear();
+        self.checksum.reset();
+        self.byte_count = 0;
+    }
+
+    /// Number of bytes a back-reference can currently reach.
+    pub fn history_len(&self) -> usize {
+        self.history.len()
+    }
 
-        // using .cycle() in case of len > dist
-        let history_slice: Vec<u8> = self
-            .history
-            .range(slice_start..slice_end)
-            .copied()
-            .cycle()
-            .take(len)
-            .collect();
+    /// Last `n` bytes of the history (fewer if it's shorter), for debugging.
+    #[cfg(feature = "debug-history")]
+    pub fn history_tail(&self, n: usize) -> Vec<u8> {
+        let start = self.history.len().saturating_sub(n);
+        self.history.range(start..).copied().collect()
+    }
 
-        self.write_all(&history_slice)
-            .context("Unable to write all slice of history bytes!")
+    pub fn inner_mut(&mut self) -> &mut T {
+        &mut self.inner
     }
 
-    pub fn byte_count(&self) -> usize {
+    pub fn byte_count(&self) -> u64 {
         self.byte_count
     }
 
-    // returns crc32 and underlying writer
-    pub fn crc32(self) -> (u32, T) {
-        (self.digest.finalize(), self.inner)
+    /// Disabling the checksum saves its per-byte cost when integrity isn't checked.
+    /// Meant to be set before anything is written.
+    pub fn set_crc_enabled(&mut self, is_enabled: bool) {
+        self.checksum.reset();
+        self.is_checksum_enabled = is_enabled;
+    }
+
+    /// Checksum of the bytes written so far, `None` if its computation is disabled.
+    pub fn checksum(&self) -> Option<u32> {
+        self.is_checksum_enabled.then(|| self.checksum.value())
+    }
+
+    /// Same as `checksum`, for the gzip writers created with the default CRC32.
+    pub fn crc32(&self) -> Option<u32> {
+        self.checksum()
     }
 }
 
@@ -88,6 +199,7 @@ impl<T: Write> TrackingWriter<T> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::checksum::Adler32;
     use byteorder::WriteBytesExt;
 
     #[test]
@@ -106,7 +218,7 @@ mod tests {
 
         assert_eq!(writer.write(&[42, 124, 234, 27])?, 0);
         assert_eq!(writer.byte_count(), 10);
-        assert_eq!(writer.crc32().0, 2992191065);
+        assert_eq!(writer.crc32(), Some(2992191065));
 
         Ok(())
     }
@@ -131,7 +243,112 @@ mod tests {
 
         assert!(writer.write_previous(1, 1).is_err());
         assert_eq!(writer.byte_count(), 512);
-        assert_eq!(writer.crc32().0, 2733545866);
+        assert_eq!(writer.crc32(), Some(2733545866));
+
+        Ok(())
+    }
+
+    #[test]
+    fn write_previous_run() -> Result<()> {
+        let mut buf = Vec::new();
+        let mut writer = TrackingWriter::new(&mut buf);
+        writer.write_all(b"ab")?;
+        writer.write_previous(1, 600)?;
+        assert_eq!(writer.byte_count(), 602);
+        drop(writer);
+        assert_eq!(&buf[..2], b"ab");
+        assert!(buf[2..].iter().all(|&byte| byte == b'b'));
+
+        let mut buf = Vec::new();
+        let mut writer = TrackingWriter::new(&mut buf);
+        writer.set_lenient_distance(true);
+        writer.write_previous(1, 3)?;
+        drop(writer);
+        assert_eq!(buf, [0, 0, 0]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn write_previous_lenient() -> Result<()> {
+        let mut buf: &mut [u8] = &mut [0u8; 10];
+        let mut writer = TrackingWriter::new(&mut buf);
+        writer.set_lenient_distance(true);
+        writer.write_all(&[1, 2])?;
+
+        writer.write_previous(4, 6)?;
+        assert_eq!(writer.byte_count(), 8);
+        assert!(writer.write_previous(HISTORY_SIZE + 1, 1).is_err());
+        assert_eq!(writer.crc32(), Some(2234482133));
+
+        Ok(())
+    }
+
+    #[test]
+    fn crc_disabled() -> Result<()> {
+        let mut buf: &mut [u8] = &mut [0u8; 10];
+        let mut writer = TrackingWriter::new(&mut buf);
+        writer.set_crc_enabled(false);
+
+        writer.write_all(&[1, 2, 3])?;
+        writer.write_previous(2, 2)?;
+        assert_eq!(writer.byte_count(), 5);
+        assert_eq!(writer.crc32(), None);
+
+        writer.reset();
+        assert_eq!(writer.crc32(), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn checksum() -> Result<()> {
+        let mut writer = TrackingWriter::new(Vec::new());
+        writer.write_all(b"123456789")?;
+        assert_eq!(writer.crc32(), Some(0xcbf43926));
+
+        let mut writer = TrackingWriter::with_checksum(Vec::new(), Adler32::new());
+        writer.write_all(b"Wiki")?;
+        writer.write_all(b"pedia")?;
+        assert_eq!(writer.checksum(), Some(0x11e60398));
+
+        writer.reset();
+        assert_eq!(writer.checksum(), Some(1));
+
+        Ok(())
+    }
+
+    #[test]
+    fn history_len() -> Result<()> {
+        let mut writer = TrackingWriter::new(Vec::new());
+        assert_eq!(writer.history_len(), 0);
+
+        writer.write_all(&[7; HISTORY_SIZE])?;
+        writer.write_all(&[1, 2, 3])?;
+        assert_eq!(writer.history_len(), HISTORY_SIZE);
+
+        #[cfg(feature = "debug-history")]
+        {
+            assert_eq!(writer.history_tail(4), [7, 1, 2, 3]);
+            assert_eq!(writer.history_tail(HISTORY_SIZE + 1).len(), HISTORY_SIZE);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn reset() -> Result<()> {
+        let mut buf: &mut [u8] = &mut [0u8; 10];
+        let mut writer = TrackingWriter::new(&mut buf);
+        writer.write_all(&[1, 2, 3, 4])?;
+
+        writer.reset();
+        assert_eq!(writer.byte_count(), 0);
+        assert!(writer.write_previous(1, 1).is_err());
+
+        writer.write_all(&[1, 2, 3, 4, 4, 8])?;
+        assert_eq!(writer.byte_count(), 6);
+        assert_eq!(writer.crc32(), Some(2136304482));
 
         Ok(())
     }
@@ -145,7 +362,36 @@ mod tests {
 
         assert!(writer.write_previous(2, 8).is_ok());
         assert_eq!(writer.byte_count(), 10);
-        assert_eq!(writer.crc32().0, 3148311779);
+        assert_eq!(writer.crc32(), Some(3148311779));
+
+        Ok(())
+    }
+
+    #[test]
+    fn write_previous_long() -> Result<()> {
+        let mut output = Vec::new();
+        let mut writer = TrackingWriter::new(&mut output);
+        writer.write_all(&[1, 2, 3])?;
+
+        writer.write_previous(3, 1000)?;
+        assert_eq!(writer.byte_count(), 1003);
+        assert!(output
+            .chunks(3)
+            .all(|chunk| chunk == &[1, 2, 3][..chunk.len()]));
+
+        Ok(())
+    }
+
+    #[test]
+    fn reuse_history() -> Result<()> {
+        let mut writer = TrackingWriter::new(Vec::new());
+        writer.write_all(&[1, 2, 3])?;
+        let history = writer.into_history();
+        let capacity = history.capacity();
+
+        let mut writer = TrackingWriter::with_history(Vec::new(), history);
+        assert!(writer.write_previous(1, 1).is_err());
+        assert_eq!(writer.into_history().capacity(), capacity);
 
         Ok(())
     }
@@ -0,0 +1,30 @@
+#![forbid(unsafe_code)]
+
+use std::io::{self, Write};
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Writer passing everything written to it through `transform` before writing the result
+/// to `inner`. The transformed data may have any length.
+pub struct TransformWriter<W, F> {
+    inner: W,
+    transform: F,
+}
+
+impl<W: Write, F: FnMut(&[u8]) -> Vec<u8>> TransformWriter<W, F> {
+    pub fn new(inner: W, transform: F) -> Self {
+        Self { inner, transform }
+    }
+}
+
+impl<W: Write, F: FnMut(&[u8]) -> Vec<u8>> Write for TransformWriter<W, F> {
+    // the whole buffer is always taken, since the transform can't be undone partially
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write_all(&(self.transform)(buf))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
@@ -0,0 +1,119 @@
+#![forbid(unsafe_code)]
+
+use std::io::{BufRead, Write};
+
+use anyhow::{bail, ensure, Context, Result};
+use byteorder::{BigEndian, ReadBytesExt};
+
+use crate::{
+    bit_reader::BitReader,
+    checksum::{Adler32, Checksum},
+    deflate::{
+        DeflateReader,
+        NextBlock::{BlockOrError, Footer},
+    },
+    tracking_writer::TrackingWriter,
+    DecompressError,
+};
+
+////////////////////////////////////////////////////////////////////////////////
+
+const CM_DEFLATE: u8 = 8;
+const MAX_CINFO: u8 = 7;
+const FDICT_OFFSET: u8 = 5;
+
+/// Adler-32 checksum used by zlib for the data and the preset dictionary id.
+pub fn adler32(data: &[u8]) -> u32 {
+    let mut adler = Adler32::new();
+    adler.update(data);
+    adler.value()
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Decoder of the zlib format (RFC 1950): a single deflate stream between a two-byte header
+/// and an Adler-32 of the data.
+#[derive(Default)]
+pub struct ZlibDecoder {
+    dictionary: Option<Vec<u8>>,
+}
+
+impl ZlibDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Preset dictionary for streams with FDICT set. Its Adler-32 has to match the id
+    /// declared in the header, otherwise decoding fails with `DictionaryMismatch`.
+    pub fn with_dictionary(mut self, dictionary: impl Into<Vec<u8>>) -> Self {
+        self.dictionary = Some(dictionary.into());
+        self
+    }
+
+    /// Returns the number of decompressed bytes.
+    pub fn decompress<R: BufRead, W: Write>(&self, mut input: R, output: W) -> Result<u64> {
+        let cmf = input.read_u8().context("Failed reading CMF!")?;
+        let flg = input.read_u8().context("Failed reading FLG!")?;
+        ensure!(
+            (u16::from(cmf) << 8 | u16::from(flg)) % 31 == 0,
+            "zlib header check failed!"
+        );
+
+        let method = cmf & 0x0f;
+        if method != CM_DEFLATE {
+            bail!(DecompressError::UnsupportedCompressionMethod(method));
+        }
+        ensure!(cmf >> 4 <= MAX_CINFO, "zlib window size is too large!");
+
+        let mut writer = TrackingWriter::with_checksum(output, Adler32::new());
+
+        if (flg >> FDICT_OFFSET) & 1 == 1 {
+            let expected = input
+                .read_u32::<BigEndian>()
+                .context("Failed reading DICTID!")?;
+            let actual = self.dictionary.as_deref().map(adler32);
+            ensure!(
+                actual == Some(expected),
+                DecompressError::DictionaryMismatch { expected, actual }
+            );
+            writer.preset_dictionary(self.dictionary.as_deref().unwrap_or_default());
+        }
+
+        let mut deflate_reader = DeflateReader::new(BitReader::new(input), writer);
+        deflate_reader.set_window_size(1 << ((cmf >> 4) + 8));
+        let (mut input, writer) = loop {
+            match deflate_reader.next_block() {
+                BlockOrError(block) => deflate_reader = block?.read_content(None)?,
+                Footer(input, writer, _) => break (input, writer),
+            }
+        };
+
+        let byte_count = writer.byte_count();
+        let adler = writer.checksum();
+        let expected = input
+            .read_u32::<BigEndian>()
+            .context("Failed reading ADLER32!")?;
+        ensure!(adler == Some(expected), "adler32 check failed!");
+
+        Ok(byte_count)
+    }
+}
+
+/// Decompresses zlib data without a preset dictionary, returns the number of decompressed
+/// bytes.
+pub fn decompress_zlib<R: BufRead, W: Write>(input: R, output: W) -> Result<u64> {
+    ZlibDecoder::new().decompress(input, output)
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn adler32_values() {
+        assert_eq!(adler32(b""), 1);
+        assert_eq!(adler32(b"Wikipedia"), 0x11e60398);
+    }
+}
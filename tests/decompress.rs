@@ -0,0 +1,1880 @@
+use std::{
+    cell::RefCell,
+    io::{BufRead, Read, Write},
+    rc::Rc,
+    time::Instant,
+};
+
+use anyhow::Result;
+use crc::{Crc, CRC_32_ISO_HDLC};
+
+use ripgzip::{
+    build_block_index, decompress, decompress_append, decompress_chunks, decompress_prefix,
+    decompress_reader, decompress_returning, decompress_slice, decompress_tee,
+    decompress_verify_against, decompress_with_options, decompress_with_tokens,
+    decompress_with_transform, decompressed_crc32, ensure_single_member, gzip_lines,
+    peek_first_block_type, rewrite_headers, rewrite_headers_with_options, BlockHeader, BlockOffset,
+    BlockTypeStats, CompressionMethod, CompressionType, DecompressError, DecompressOptions,
+    Decompressor, LineEnding, Lz77Token, MemberFooter, MemberHeader, NullWriter, Observer,
+    RewriteOptions, TextMode, DEFAULT_MAX_STRING_LEN, OUTPUT_CHUNK_SIZE,
+};
+
+const FTEXT: u8 = 1;
+const FNAME: u8 = 1 << 3;
+
+// builds a gzip member containing `data` in a single stored block
+fn stored_member(flags: u8, data: &[u8]) -> Vec<u8> {
+    let mut member = vec![0x1f, 0x8b, 8, flags, 0, 0, 0, 0, 0, 255];
+
+    member.push(1);
+    member.extend((data.len() as u16).to_le_bytes());
+    member.extend((!(data.len() as u16)).to_le_bytes());
+    member.extend(data);
+
+    let crc = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+    member.extend(crc.checksum(data).to_le_bytes());
+    member.extend((data.len() as u32).to_le_bytes());
+    member
+}
+
+#[test]
+fn output_size() -> Result<()> {
+    let mut input = stored_member(0, b"abcd");
+    input.extend(include_bytes!("../data/ok/11-fixed-tree.gz"));
+
+    assert_eq!(decompress(input.as_slice(), std::io::sink())?, 16);
+
+    let mut writer = NullWriter::new();
+    decompress(input.as_slice(), &mut writer)?;
+    assert_eq!(writer.byte_count(), 16);
+
+    let stats = decompress_with_options(
+        input.as_slice(),
+        std::io::sink(),
+        &DecompressOptions::default(),
+    )?;
+    assert_eq!(stats.members[0].output_size, 4);
+    assert_eq!(stats.members[1].output_size, 12);
+
+    Ok(())
+}
+
+#[test]
+fn compressed_size() -> Result<()> {
+    let fixed_tree = include_bytes!("../data/ok/11-fixed-tree.gz");
+    let mut input = stored_member(0, b"abcd");
+    input.extend(fixed_tree);
+
+    let stats = decompress_with_options(
+        input.as_slice(),
+        std::io::sink(),
+        &DecompressOptions::default(),
+    )?;
+    assert_eq!(stats.members[0].compressed_size, 27);
+    assert_eq!(stats.members[1].compressed_size, fixed_tree.len() as u64);
+    assert_eq!(stats.members[0].compression_ratio(), 4.0 / 27.0);
+
+    Ok(())
+}
+
+#[test]
+fn computed_crc32() -> Result<()> {
+    let input = stored_member(0, b"abcd");
+
+    let stats = decompress_with_options(
+        input.as_slice(),
+        std::io::sink(),
+        &DecompressOptions::default(),
+    )?;
+    let member = &stats.members[0];
+    assert_eq!(member.computed_crc32, Some(0xed82cd11));
+    assert_eq!(member.computed_crc32, Some(member.footer.data_crc32));
+
+    let options = DecompressOptions {
+        skip_crc32: true,
+        ..Default::default()
+    };
+    let stats = decompress_with_options(input.as_slice(), std::io::sink(), &options)?;
+    assert_eq!(stats.members[0].computed_crc32, None);
+
+    Ok(())
+}
+
+#[test]
+fn slice() -> Result<()> {
+    let mut output = Vec::new();
+    decompress_slice(&stored_member(0, b"slice"), &mut output)?;
+    assert_eq!(output, b"slice");
+
+    Ok(())
+}
+
+#[test]
+fn normalize_newlines() -> Result<()> {
+    let mut input = stored_member(FTEXT, b"a\r\nb\r");
+    input.extend(stored_member(0, b"c\r\n"));
+
+    let options = DecompressOptions {
+        text_mode: TextMode::ConvertNewlines(LineEnding::Lf),
+        ..Default::default()
+    };
+    let mut output = Vec::new();
+    let stats = decompress_with_options(input.as_slice(), &mut output, &options)?;
+
+    // footers are checked against the bytes before the conversion
+    assert_eq!(output, b"a\nb\nc\r\n");
+    assert!(stats.members[0].is_text());
+    assert!(!stats.members[1].is_text());
+
+    Ok(())
+}
+
+#[test]
+fn convert_newlines_to_crlf() -> Result<()> {
+    let mut input = stored_member(FTEXT, b"a\nb\r\n");
+    input.extend(stored_member(0, b"c\n"));
+
+    let options = DecompressOptions {
+        text_mode: TextMode::ConvertNewlines(LineEnding::CrLf),
+        ..Default::default()
+    };
+    let mut output = Vec::new();
+    decompress_with_options(input.as_slice(), &mut output, &options)?;
+    assert_eq!(output, b"a\r\nb\r\nc\n");
+
+    // text members are written as they are by default
+    let mut output = Vec::new();
+    decompress(input.as_slice(), &mut output)?;
+    assert_eq!(output, b"a\nb\r\nc\n");
+
+    Ok(())
+}
+
+#[test]
+fn stop_at_unknown_method() -> Result<()> {
+    let mut input = stored_member(0, b"data");
+    let mut unknown_member = stored_member(0, b"trailing");
+    unknown_member[2] = 7;
+    input.extend(unknown_member);
+
+    let mut output = Vec::new();
+    let error =
+        decompress_with_options(input.as_slice(), &mut output, &DecompressOptions::default())
+            .unwrap_err();
+    assert_eq!(
+        error.downcast_ref(),
+        Some(&DecompressError::UnsupportedCompressionMethod(7))
+    );
+
+    let options = DecompressOptions {
+        stop_at_unknown_method: true,
+        ..Default::default()
+    };
+    output.clear();
+    let stats = decompress_with_options(input.as_slice(), &mut output, &options)?;
+
+    assert_eq!(output, b"data");
+    assert_eq!(stats.members.len(), 1);
+    assert!(stats.stopped_at_unknown_method);
+
+    Ok(())
+}
+
+#[test]
+fn collect_block_trees() -> Result<()> {
+    let input = include_bytes!("../data/ok/00-Cargo.toml.gz");
+
+    let stats = decompress_with_options(
+        input.as_slice(),
+        std::io::sink(),
+        &DecompressOptions::default(),
+    )?;
+    assert!(stats.members[0].block_trees.is_empty());
+
+    let options = DecompressOptions {
+        collect_block_trees: true,
+        ..Default::default()
+    };
+    let stats = decompress_with_options(input.as_slice(), std::io::sink(), &options)?;
+    let trees = &stats.members[0].block_trees[0];
+
+    let used_lengths = trees.code_lengths.litlen.iter().filter(|&&len| len != 0);
+    assert_eq!(trees.litlen.len(), used_lengths.count());
+    assert!(trees.litlen.iter().any(|entry| entry.symbol == 256));
+    assert!(trees.to_string().starts_with("literal/length codes:\n"));
+
+    Ok(())
+}
+
+#[test]
+fn tokens() -> Result<()> {
+    let input = include_bytes!("../data/ok/11-fixed-tree.gz");
+    let mut tokens = Vec::new();
+    let mut output = Vec::new();
+
+    decompress_with_tokens(
+        input.as_slice(),
+        &mut output,
+        &DecompressOptions::default(),
+        |token| tokens.push(token),
+    )?;
+
+    assert_eq!(output, b"abcabcabcabc");
+    assert_eq!(
+        tokens,
+        [
+            Lz77Token::Literal(b'a'),
+            Lz77Token::Literal(b'b'),
+            Lz77Token::Literal(b'c'),
+            Lz77Token::Literal(b'a'),
+            Lz77Token::Match {
+                length: 8,
+                distance: 3
+            },
+        ]
+    );
+
+    let mut tokens = Vec::new();
+    decompress_with_tokens(
+        stored_member(0, b"xy").as_slice(),
+        std::io::sink(),
+        &DecompressOptions::default(),
+        |token| tokens.push(token),
+    )?;
+    assert_eq!(tokens, [Lz77Token::Literal(b'x'), Lz77Token::Literal(b'y')]);
+
+    Ok(())
+}
+
+struct EventLog(Rc<RefCell<Vec<String>>>);
+
+impl Observer for EventLog {
+    fn on_member_start(&self, header: &MemberHeader) {
+        self.0
+            .borrow_mut()
+            .push(format!("member {:?}", header.name));
+    }
+
+    fn on_block(&self, header: &BlockHeader) {
+        self.0.borrow_mut().push(format!(
+            "block {} {:?} final={}",
+            header.index, header.compression_type, header.is_final
+        ));
+    }
+
+    fn on_member_end(&self, footer: &MemberFooter) {
+        self.0
+            .borrow_mut()
+            .push(format!("end {}", footer.data_size));
+    }
+
+    fn on_bytes(&self, input: u64, output: u64) {
+        self.0.borrow_mut().push(format!("bytes {input} {output}"));
+    }
+}
+
+#[test]
+fn observer() -> Result<()> {
+    // the first member is split into a non-final and a final stored block
+    let mut input = stored_member(0, b"abcd");
+    input.splice(
+        10..17,
+        [0, 2, 0, 0xfd, 0xff, b'a', b'b', 1, 2, 0, 0xfd, 0xff],
+    );
+    input.extend(include_bytes!("../data/ok/11-fixed-tree.gz"));
+
+    let events = Rc::new(RefCell::new(Vec::new()));
+    let options = DecompressOptions {
+        observer: Some(Box::new(EventLog(events.clone()))),
+        ..Default::default()
+    };
+    decompress_with_options(input.as_slice(), std::io::sink(), &options)?;
+
+    assert_eq!(
+        *events.borrow(),
+        [
+            "member None",
+            "block 0 Uncompressed final=false",
+            "bytes 17 2",
+            "block 1 Uncompressed final=true",
+            "bytes 7 2",
+            "bytes 8 0",
+            "end 4",
+            "member None",
+            "block 0 FixedTree final=true",
+            "bytes 17 12",
+            "bytes 8 0",
+            "end 12",
+        ]
+    );
+
+    Ok(())
+}
+
+struct CompressedData(Rc<RefCell<Vec<Vec<u8>>>>);
+
+impl Observer for CompressedData {
+    fn on_compressed_data(&self, data: &[u8]) {
+        self.0.borrow_mut().push(data.to_vec());
+    }
+}
+
+#[test]
+fn observe_compressed_data() -> Result<()> {
+    let mut input = stored_member(0, b"abcd");
+    input.splice(
+        10..17,
+        [0, 2, 0, 0xfd, 0xff, b'a', b'b', 1, 2, 0, 0xfd, 0xff],
+    );
+    let fixed_tree = include_bytes!("../data/ok/11-fixed-tree.gz");
+    input.extend(fixed_tree);
+
+    let pieces = Rc::new(RefCell::new(Vec::new()));
+    let options = DecompressOptions {
+        observer: Some(Box::new(CompressedData(pieces.clone()))),
+        observe_compressed_data: true,
+        ..Default::default()
+    };
+    decompress_with_options(input.as_slice(), std::io::sink(), &options)?;
+
+    // a piece per block, from the end of the header up to the footer
+    assert_eq!(
+        *pieces.borrow(),
+        [
+            input[10..17].to_vec(),
+            input[17..24].to_vec(),
+            fixed_tree[10..fixed_tree.len() - 8].to_vec(),
+        ]
+    );
+
+    // nothing is recorded unless asked for
+    pieces.borrow_mut().clear();
+    let options = DecompressOptions {
+        observer: Some(Box::new(CompressedData(pieces.clone()))),
+        ..Default::default()
+    };
+    decompress_with_options(input.as_slice(), std::io::sink(), &options)?;
+    assert!(pieces.borrow().is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn block_types() -> Result<()> {
+    let mut input = stored_member(0, b"abcd");
+    input.splice(
+        10..17,
+        [0, 2, 0, 0xfd, 0xff, b'a', b'b', 1, 2, 0, 0xfd, 0xff],
+    );
+    input.extend(include_bytes!("../data/ok/11-fixed-tree.gz"));
+    input.extend(include_bytes!("../data/ok/00-Cargo.toml.gz"));
+
+    let stats = decompress_with_options(input.as_slice(), std::io::sink(), &Default::default())?;
+    let block_types = stats.block_types;
+    assert_eq!(
+        block_types.stored,
+        BlockTypeStats {
+            blocks: 2,
+            output_size: 4
+        }
+    );
+    assert_eq!(
+        block_types.fixed,
+        BlockTypeStats {
+            blocks: 1,
+            output_size: 12
+        }
+    );
+    assert_eq!(block_types.dynamic.blocks, 1);
+    assert_eq!(
+        block_types.dynamic.output_size,
+        stats.members[2].output_size
+    );
+
+    Ok(())
+}
+
+#[test]
+fn collect_distances() -> Result<()> {
+    let input = include_bytes!("../data/ok/11-fixed-tree.gz");
+    let options = DecompressOptions {
+        collect_distances: true,
+        ..Default::default()
+    };
+    let stats = decompress_with_options(input.as_slice(), std::io::sink(), &options)?;
+
+    // "abc" followed by a single match
+    let distances = stats.distances.unwrap();
+    assert_eq!(distances.matches(), 1);
+    assert_eq!(distances.distinct(), 1);
+    assert!(distances.contains(3));
+
+    let stats = decompress_with_options(input.as_slice(), std::io::sink(), &Default::default())?;
+    assert!(stats.distances.is_none());
+
+    Ok(())
+}
+
+#[test]
+fn block_index() -> Result<()> {
+    let mut input = stored_member(0, b"abcd");
+    input.splice(
+        10..17,
+        [0, 2, 0, 0xfd, 0xff, b'a', b'b', 1, 2, 0, 0xfd, 0xff],
+    );
+    input.extend(include_bytes!("../data/ok/11-fixed-tree.gz"));
+
+    let offset = |member, bit_offset, is_final, compression_type| BlockOffset {
+        member,
+        bit_offset,
+        is_final,
+        compression_type,
+    };
+    assert_eq!(
+        build_block_index(input.as_slice())?,
+        [
+            offset(0, 80, false, CompressionType::Uncompressed),
+            offset(0, 136, true, CompressionType::Uncompressed),
+            offset(1, 336, true, CompressionType::FixedTree),
+        ]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn end_offset() -> Result<()> {
+    let mut input = stored_member(0, b"abcd");
+    input.extend(include_bytes!("../data/ok/11-fixed-tree.gz"));
+    let gzip_len = input.len() as u64;
+
+    let stats = decompress_with_options(input.as_slice(), std::io::sink(), &Default::default())?;
+    assert_eq!(stats.end_offset, gzip_len);
+
+    let mut trailer = stored_member(0, b"rest");
+    trailer[2] = 7;
+    input.extend(trailer);
+    let options = DecompressOptions {
+        stop_at_unknown_method: true,
+        ..Default::default()
+    };
+    let stats = decompress_with_options(input.as_slice(), std::io::sink(), &options)?;
+    assert_eq!(stats.end_offset, gzip_len);
+
+    Ok(())
+}
+
+#[test]
+fn trailing_non_gzip_data() -> Result<()> {
+    let mut input = stored_member(0, b"data");
+    let gzip_len = input.len() as u64;
+    input.extend(b"metadata: 42");
+
+    let error = decompress(input.as_slice(), std::io::sink()).unwrap_err();
+    assert_eq!(
+        error.downcast_ref(),
+        Some(&DecompressError::TrailingNonGzipData {
+            offset: gzip_len,
+            first_bytes: b"metadata".to_vec()
+        })
+    );
+
+    // the first member is expected to be gzip
+    let error = decompress(b"metadata".as_slice(), std::io::sink()).unwrap_err();
+    assert_eq!(error.downcast_ref::<DecompressError>(), None);
+
+    Ok(())
+}
+
+#[test]
+fn max_members() -> Result<()> {
+    let input = stored_member(0, b"").repeat(3);
+
+    let options = DecompressOptions {
+        max_members: Some(2),
+        ..Default::default()
+    };
+    let error = decompress_with_options(input.as_slice(), std::io::sink(), &options).unwrap_err();
+    assert_eq!(
+        error.downcast_ref(),
+        Some(&DecompressError::TooManyMembers { limit: 2 })
+    );
+
+    let options = DecompressOptions {
+        max_members: Some(3),
+        ..Default::default()
+    };
+    let stats = decompress_with_options(input.as_slice(), std::io::sink(), &options)?;
+    assert_eq!(stats.members.len(), 3);
+
+    Ok(())
+}
+
+#[test]
+fn deadline() -> Result<()> {
+    let input = include_bytes!("../data/ok/00-Cargo.toml.gz");
+
+    let options = DecompressOptions {
+        deadline: Some(Instant::now()),
+        ..Default::default()
+    };
+    let error = decompress_with_options(input.as_slice(), std::io::sink(), &options).unwrap_err();
+    assert_eq!(error.downcast_ref(), Some(&DecompressError::Timeout));
+
+    let options = DecompressOptions {
+        deadline: Some(Instant::now() + std::time::Duration::from_secs(3600)),
+        ..Default::default()
+    };
+    decompress_with_options(input.as_slice(), std::io::sink(), &options)?;
+
+    Ok(())
+}
+
+#[test]
+fn skip_crc32() -> Result<()> {
+    let input = include_bytes!("../data/corrupted/01-bad-crc32.gz");
+    assert!(decompress_slice(input, std::io::sink()).is_err());
+
+    let options = DecompressOptions {
+        skip_crc32: true,
+        ..Default::default()
+    };
+    decompress_with_options(input.as_slice(), std::io::sink(), &options)?;
+
+    Ok(())
+}
+
+#[test]
+fn long_stored_block() -> Result<()> {
+    let data: Vec<u8> = (0..20000u32).map(|i| (i % 251) as u8).collect();
+
+    let mut output = Vec::new();
+    assert_eq!(
+        decompress(stored_member(0, &data).as_slice(), &mut output)?,
+        20000
+    );
+    assert_eq!(output, data);
+
+    Ok(())
+}
+
+#[test]
+fn nlen_checked_before_writing() {
+    let mut input = stored_member(0, &[42; 10000]);
+    input[13] ^= 1;
+
+    let mut output = Vec::new();
+    let error = decompress(input.as_slice(), &mut output).unwrap_err();
+    assert!(format!("{:#}", error).contains("nlen check failed"));
+    assert!(output.is_empty());
+}
+
+#[test]
+fn truncated_final_member() -> Result<()> {
+    let fixed_tree = include_bytes!("../data/ok/11-fixed-tree.gz");
+    let mut input = stored_member(0, b"abcd");
+    input.extend(&fixed_tree[..fixed_tree.len() - 6]);
+
+    assert!(decompress(input.as_slice(), std::io::sink()).is_err());
+
+    let options = DecompressOptions {
+        allow_truncated_final_member: true,
+        ..Default::default()
+    };
+    let mut output = Vec::new();
+    let stats = decompress_with_options(input.as_slice(), &mut output, &options)?;
+    assert_eq!(output, b"abcdabcabcabcabc");
+    assert_eq!(stats.members.len(), 1);
+    assert!(stats.truncated_final_member);
+
+    // corrupted data isn't mistaken for truncation
+    let mut input = stored_member(0, b"abcd");
+    input[13] ^= 1;
+    assert!(decompress_with_options(input.as_slice(), std::io::sink(), &options).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn ignore_header_crc16() -> Result<()> {
+    let input = include_bytes!("../data/corrupted/05-bad-header-crc16.gz");
+
+    let error = decompress(input.as_slice(), std::io::sink()).unwrap_err();
+    assert!(matches!(
+        error.downcast_ref(),
+        Some(DecompressError::HeaderCrcMismatch { .. })
+    ));
+
+    let options = DecompressOptions {
+        ignore_header_crc16: true,
+        ..Default::default()
+    };
+    decompress_with_options(input.as_slice(), std::io::sink(), &options)?;
+
+    Ok(())
+}
+
+struct RawPayload(Rc<RefCell<Vec<u8>>>);
+
+impl Observer for RawPayload {
+    fn on_raw_payload(&self, header: &MemberHeader, data: &[u8]) {
+        assert!(matches!(
+            header.compression_method,
+            CompressionMethod::Unknown(7)
+        ));
+        self.0.borrow_mut().extend(data);
+    }
+}
+
+#[test]
+fn pass_through_unknown_method() -> Result<()> {
+    let mut input = stored_member(0, b"data");
+    input.extend([0x1f, 0x8b, 7, 0, 0, 0, 0, 0, 0, 255]);
+    input.extend(b"raw payload");
+
+    let payload = Rc::new(RefCell::new(Vec::new()));
+    let options = DecompressOptions {
+        pass_through_unknown_method: true,
+        observer: Some(Box::new(RawPayload(payload.clone()))),
+        ..Default::default()
+    };
+    let mut output = Vec::new();
+    let stats = decompress_with_options(input.as_slice(), &mut output, &options)?;
+
+    assert_eq!(output, b"data");
+    assert_eq!(*payload.borrow(), b"raw payload");
+    assert_eq!(stats.members.len(), 1);
+    assert!(stats.stopped_at_unknown_method);
+
+    Ok(())
+}
+
+#[test]
+fn empty_member() -> Result<()> {
+    let input = include_bytes!("../data/ok/12-empty.gz");
+
+    let mut output = Vec::new();
+    let mut tokens = Vec::new();
+    let stats = decompress_with_tokens(
+        input.as_slice(),
+        &mut output,
+        &DecompressOptions::default(),
+        |token| tokens.push(token),
+    )?;
+
+    assert!(output.is_empty());
+    assert!(tokens.is_empty());
+    assert_eq!(stats.members.len(), 1);
+    assert_eq!(stats.members[0].output_size, 0);
+    assert_eq!(stats.members[0].computed_crc32, Some(0));
+
+    Ok(())
+}
+
+#[test]
+fn present_fields() -> Result<()> {
+    let input = include_bytes!("../data/ok/10-header-crc16.gz");
+
+    let stats = decompress_with_options(
+        input.as_slice(),
+        std::io::sink(),
+        &DecompressOptions::default(),
+    )?;
+    let fields = stats.members[0].present_fields();
+    assert!(fields.has_crc());
+    assert!(fields.has_extra());
+    assert!(!fields.has_name() && !fields.has_comment());
+
+    Ok(())
+}
+
+// FHCRC is computed over the header bytes as they are in the input
+#[test]
+fn header_crc16_non_ascii_name() -> Result<()> {
+    const FHCRC: u8 = 2;
+    // ISO-8859-1, not valid UTF-8
+    let name = b"na\xefve.txt";
+    let mut input = stored_member(FNAME | FHCRC, b"data");
+    input.splice(10..10, name.iter().copied().chain([0]));
+
+    let header_len = 10 + name.len() + 1;
+    let crc16 = Crc::<u32>::new(&CRC_32_ISO_HDLC).checksum(&input[..header_len]) as u16;
+    input.splice(header_len..header_len, crc16.to_le_bytes());
+
+    let stats = decompress_with_options(
+        input.as_slice(),
+        std::io::sink(),
+        &DecompressOptions::default(),
+    )?;
+    let header = &stats.members[0].header;
+    assert_eq!(header.name.as_deref(), Some("naïve.txt"));
+    assert_eq!(header.to_bytes(), input[..header_len + 2]);
+
+    input[header_len] ^= 1;
+    let error = decompress(input.as_slice(), std::io::sink()).unwrap_err();
+    assert!(matches!(
+        error.downcast_ref(),
+        Some(DecompressError::HeaderCrcMismatch { .. })
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn member_name() -> Result<()> {
+    let mut input = stored_member(FNAME, b"data");
+    input.splice(10..10, *b"file.txt\0");
+
+    let stats = decompress_with_options(
+        input.as_slice(),
+        std::io::sink(),
+        &DecompressOptions::default(),
+    )?;
+    assert_eq!(stats.members[0].header.name.as_deref(), Some("file.txt"));
+
+    Ok(())
+}
+
+#[test]
+fn unterminated_name() {
+    let mut input = stored_member(FNAME, b"");
+    input.truncate(10);
+    input.extend(b"file.txt");
+
+    let error = decompress(input.as_slice(), std::io::sink()).unwrap_err();
+    assert_eq!(
+        error.downcast_ref(),
+        Some(&DecompressError::UnterminatedString { field: "name" })
+    );
+}
+
+#[test]
+fn returning_writer() -> Result<()> {
+    let mut input = stored_member(0, b"abcd");
+    input.extend(include_bytes!("../data/ok/11-fixed-tree.gz"));
+
+    let output = decompress_returning(input.as_slice(), b"prefix ".to_vec())?;
+    assert_eq!(output.len(), 7 + 16);
+    assert!(output.starts_with(b"prefix abcd"));
+
+    Ok(())
+}
+
+#[test]
+fn tee() -> Result<()> {
+    let input = include_bytes!("../data/ok/09-concat.gz");
+    let expected = decompress_returning(input.as_slice(), Vec::new())?;
+
+    let mut primary = Vec::new();
+    let mut secondary = NullWriter::new();
+    let size = decompress_tee(input.as_slice(), &mut primary, &mut secondary)?;
+    assert_eq!(size, expected.len() as u64);
+    assert_eq!(primary, expected);
+    assert_eq!(secondary.byte_count(), size);
+
+    Ok(())
+}
+
+#[test]
+fn empty_final_block() -> Result<()> {
+    // the data in a non-final stored block, followed by a final fixed block with only
+    // the end-of-block code
+    let mut input = stored_member(0, b"data");
+    input[10] = 0;
+    input.splice(19..19, [0b011, 0]);
+    input.extend(stored_member(0, b"more"));
+
+    let mut output = Vec::new();
+    let stats = decompress_with_options(input.as_slice(), &mut output, &Default::default())?;
+    assert_eq!(output, b"datamore");
+    assert_eq!(stats.members.len(), 2);
+    assert_eq!(stats.members[0].footer.data_size, 4);
+
+    Ok(())
+}
+
+#[test]
+fn check_length_only() -> Result<()> {
+    assert!(ripgzip::check_length_only(
+        include_bytes!("../data/ok/09-concat.gz").as_slice()
+    )?);
+    assert!(!ripgzip::check_length_only(
+        include_bytes!("../data/corrupted/00-bad-length.gz").as_slice()
+    )?);
+    // CRC32 isn't checked
+    assert!(ripgzip::check_length_only(
+        include_bytes!("../data/corrupted/01-bad-crc32.gz").as_slice()
+    )?);
+    assert!(ripgzip::check_length_only(
+        include_bytes!("../data/corrupted/08-bad-nlen.gz").as_slice()
+    )
+    .is_err());
+
+    Ok(())
+}
+
+#[test]
+fn verify_against() -> Result<()> {
+    let crc = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+    let input = stored_member(0, b"data");
+    let data_crc32 = crc.checksum(b"data");
+
+    decompress_verify_against(input.as_slice(), std::io::sink(), data_crc32, 4)?;
+
+    let error =
+        decompress_verify_against(input.as_slice(), std::io::sink(), data_crc32, 5).unwrap_err();
+    assert!(matches!(
+        error.downcast_ref(),
+        Some(DecompressError::ExpectedFooterMismatch { footer_size: 4, .. })
+    ));
+
+    // with several members only the whole output can be compared
+    let mut input = stored_member(0, b"da");
+    input.extend(stored_member(0, b"ta"));
+    decompress_verify_against(input.as_slice(), std::io::sink(), data_crc32, 4)?;
+
+    let error =
+        decompress_verify_against(input.as_slice(), std::io::sink(), !data_crc32, 4).unwrap_err();
+    assert_eq!(
+        error.downcast_ref(),
+        Some(&DecompressError::ExpectedOutputMismatch {
+            expected_crc32: !data_crc32,
+            expected_size: 4,
+            actual_crc32: data_crc32,
+            actual_size: 4
+        })
+    );
+
+    Ok(())
+}
+
+#[test]
+fn padding_bits() -> Result<()> {
+    // a final fixed block with only the end-of-block code takes 10 bits, leaving 6 bits
+    // of padding, here set to 0b101010
+    let mut input = stored_member(0, b"");
+    input.splice(10..15, [0b011, 0b10101000]);
+
+    let stats = decompress_with_options(input.as_slice(), std::io::sink(), &Default::default())?;
+    let padding = stats.members[0].padding_bits;
+    assert_eq!((padding.len(), padding.bits()), (6, 0b101010));
+
+    let stats = decompress_with_options(
+        stored_member(0, b"data").as_slice(),
+        std::io::sink(),
+        &Default::default(),
+    )?;
+    assert_eq!(stats.members[0].padding_bits.len(), 0);
+
+    Ok(())
+}
+
+#[test]
+fn prefix() -> Result<()> {
+    let input = include_bytes!("../data/ok/09-concat.gz");
+    let expected = decompress_returning(input.as_slice(), Vec::new())?;
+
+    for max_bytes in [0, 1, 5, expected.len() as u64 - 1] {
+        let mut output = Vec::new();
+        assert_eq!(
+            decompress_prefix(input.as_slice(), &mut output, max_bytes)?,
+            max_bytes
+        );
+        assert_eq!(output, expected[..max_bytes as usize]);
+    }
+
+    let mut output = Vec::new();
+    let size = decompress_prefix(input.as_slice(), &mut output, u64::MAX)?;
+    assert_eq!(size, expected.len() as u64);
+    assert_eq!(output, expected);
+
+    // the part before the limit is still decoded and corrupted data is reported
+    let corrupted = include_bytes!("../data/corrupted/08-bad-nlen.gz");
+    assert!(decompress_prefix(corrupted.as_slice(), std::io::sink(), 1 << 20).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn no_distance_codes() -> Result<()> {
+    // dynamic block with codes only for 'a' and end of block, HDIST = 0 and the only
+    // distance code length being zero
+    let block = [5, 192, 129, 8, 0, 0, 0, 0, 32, 214, 253, 37, 14, 1];
+    let mut input = stored_member(0, b"aaa");
+    input.splice(10..18, block);
+
+    let mut output = Vec::new();
+    decompress(input.as_slice(), &mut output)?;
+    assert_eq!(output, b"aaa");
+
+    Ok(())
+}
+
+#[test]
+fn transform() -> Result<()> {
+    // back-references in the fixed tree member have to see the original bytes
+    let input = include_bytes!("../data/ok/09-concat.gz");
+    let expected = decompress_returning(input.as_slice(), Vec::new())?;
+
+    let mut output = Vec::new();
+    let size = decompress_with_transform(input.as_slice(), &mut output, |data| {
+        data.to_ascii_uppercase()
+    })?;
+    assert_eq!(size, expected.len() as u64);
+    assert_eq!(output, expected.to_ascii_uppercase());
+
+    let mut output = Vec::new();
+    decompress_with_transform(input.as_slice(), &mut output, |data| {
+        data.iter()
+            .filter(|&&byte| byte != b'\n')
+            .copied()
+            .collect()
+    })?;
+    assert!(!output.contains(&b'\n'));
+
+    Ok(())
+}
+
+#[test]
+fn max_string_len() -> Result<()> {
+    let mut input = stored_member(FNAME, b"data");
+    input.splice(10..10, *b"file.txt\0");
+
+    let options = DecompressOptions {
+        max_string_len: Some(7),
+        ..Default::default()
+    };
+    let error = decompress_with_options(input.as_slice(), std::io::sink(), &options).unwrap_err();
+    assert_eq!(
+        error.downcast_ref(),
+        Some(&DecompressError::StringTooLong {
+            field: "name",
+            limit: 7
+        })
+    );
+
+    let options = DecompressOptions {
+        max_string_len: Some(8),
+        ..Default::default()
+    };
+    decompress_with_options(input.as_slice(), std::io::sink(), &options)?;
+
+    let mut input = stored_member(FNAME, b"");
+    input.truncate(10);
+    input.resize(10 + DEFAULT_MAX_STRING_LEN as usize + 1, b'a');
+    let error = decompress(input.as_slice(), std::io::sink()).unwrap_err();
+    assert!(matches!(
+        error.downcast_ref(),
+        Some(DecompressError::StringTooLong { .. })
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn extract_all_to_dir() -> Result<()> {
+    let dir = std::env::temp_dir().join(format!("ripgzip-extract-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir)?;
+
+    let header = MemberHeader::builder()
+        .name("file.txt")
+        .modification_time(1234567)
+        .build();
+    let mut input = stored_member(0, b"named");
+    input.splice(..10, header.to_bytes());
+    input.extend(stored_member(0, b"unnamed"));
+    let mut unsafe_name = stored_member(0, b"unsafe");
+    unsafe_name.splice(
+        ..10,
+        MemberHeader::builder().name("../x").build().to_bytes(),
+    );
+    input.extend(unsafe_name);
+    // neither a duplicate name nor a name taking the place of a fallback one overwrites a file
+    let mut duplicate = stored_member(0, b"duplicate");
+    duplicate.splice(..10, header.to_bytes());
+    input.extend(duplicate);
+    let mut fallback_name = stored_member(0, b"named member_5");
+    fallback_name.splice(
+        ..10,
+        MemberHeader::builder().name("member_5").build().to_bytes(),
+    );
+    input.extend(fallback_name);
+    input.extend(stored_member(0, b"unnamed member_5"));
+
+    let manifest = ripgzip::extract_all_to_dir(input.as_slice(), &dir)?;
+    assert_eq!(
+        manifest,
+        [
+            (dir.join("file.txt"), 5),
+            (dir.join("member_1"), 7),
+            (dir.join("member_2"), 6),
+            (dir.join("file.txt.3"), 9),
+            (dir.join("member_5"), 14),
+            (dir.join("member_5.5"), 16),
+        ]
+    );
+    assert_eq!(std::fs::read(dir.join("file.txt"))?, b"named");
+    assert_eq!(std::fs::read(dir.join("member_2"))?, b"unsafe");
+    assert_eq!(std::fs::read(dir.join("file.txt.3"))?, b"duplicate");
+    assert_eq!(std::fs::read(dir.join("member_5"))?, b"named member_5");
+
+    // the renamed file exists as well after the first extraction
+    assert!(ripgzip::extract_all_to_dir(input.as_slice(), &dir).is_err());
+    assert_eq!(std::fs::read(dir.join("file.txt"))?, b"named");
+
+    let modified = std::fs::metadata(dir.join("file.txt"))?.modified()?;
+    assert_eq!(
+        modified,
+        std::time::UNIX_EPOCH + std::time::Duration::from_secs(1234567)
+    );
+
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[test]
+fn split_members() -> Result<()> {
+    let mut input = stored_member(0, b"first");
+    let mut named = stored_member(0, b"second");
+    named.splice(
+        ..10,
+        MemberHeader::builder().name("b.txt").build().to_bytes(),
+    );
+    input.extend(named);
+    input.extend(stored_member(0, b""));
+
+    let mut members = Vec::new();
+    ripgzip::split_members(input.as_slice(), |header, data| {
+        members.push((header.name, data.to_vec()));
+    })?;
+    assert_eq!(
+        members,
+        [
+            (None, b"first".to_vec()),
+            (Some("b.txt".to_string()), b"second".to_vec()),
+            (None, Vec::new()),
+        ]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn rewrite_headers_unchanged() -> Result<()> {
+    for input in [
+        include_bytes!("../data/ok/09-concat.gz").as_slice(),
+        include_bytes!("../data/ok/10-header-crc16.gz").as_slice(),
+    ] {
+        let mut output = Vec::new();
+        rewrite_headers(input, &mut output, |_| {})?;
+        assert_eq!(output, input);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn rewrite_headers_reproducible() -> Result<()> {
+    let header = MemberHeader::builder()
+        .name("file.txt")
+        .modification_time(1234567)
+        .build();
+    let mut input = stored_member(0, b"data");
+    input.splice(..10, header.to_bytes());
+    input.extend(stored_member(0, b"more"));
+
+    let mut output = Vec::new();
+    rewrite_headers(input.as_slice(), &mut output, |header| {
+        header.name = None;
+        header.modification_time = 0;
+    })?;
+
+    let mut expected = stored_member(0, b"data");
+    expected.extend(stored_member(0, b"more"));
+    assert_eq!(output, expected);
+
+    Ok(())
+}
+
+#[test]
+fn rewrite_os_and_extra_flags() -> Result<()> {
+    let input = include_bytes!("../data/ok/10-header-crc16.gz");
+    let rewrite_options = RewriteOptions {
+        os: Some(3),
+        extra_flags: Some(2),
+    };
+
+    let mut output = Vec::new();
+    rewrite_headers_with_options(input.as_slice(), &mut output, &rewrite_options, |_| {})?;
+
+    // header CRC16 is recomputed, otherwise decompression would fail
+    let stats = decompress_with_options(output.as_slice(), std::io::sink(), &Default::default())?;
+    let original = decompress_with_options(input.as_slice(), std::io::sink(), &Default::default())?;
+    for (member, original) in stats.members.iter().zip(&original.members) {
+        assert!(member.header.has_crc);
+        assert_eq!((member.header.os, member.header.extra_flags), (3, 2));
+        assert_eq!(member.header.name, original.header.name);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn make_reproducible() -> Result<()> {
+    let rewrite = |header: MemberHeader| -> Result<Vec<u8>> {
+        let mut input = stored_member(0, b"data");
+        input.splice(..10, header.to_bytes());
+
+        let mut output = Vec::new();
+        rewrite_headers(
+            input.as_slice(),
+            &mut output,
+            MemberHeader::make_reproducible,
+        )?;
+        Ok(output)
+    };
+
+    let first = rewrite(
+        MemberHeader::builder()
+            .name("a.txt")
+            .modification_time(1)
+            .os(3)
+            .build(),
+    )?;
+    let second = rewrite(
+        MemberHeader::builder()
+            .name("b.txt")
+            .modification_time(2)
+            .extra_flags(2)
+            .os(0)
+            .build(),
+    )?;
+    assert_eq!(first, second);
+    assert_eq!(first, stored_member(0, b"data"));
+
+    Ok(())
+}
+
+#[test]
+fn lines() -> Result<()> {
+    let mut input = stored_member(0, b"first\nsecond\r\nthi");
+    input.extend(stored_member(0, b"rd\n\nlast"));
+
+    let lines = gzip_lines(std::io::Cursor::new(input)).collect::<Result<Vec<_>>>()?;
+    assert_eq!(lines, ["first", "second", "third", "", "last"]);
+
+    // only CR before LF is a part of the line ending
+    let input = stored_member(0, b"a\rb\r\nlast\r");
+    let lines = gzip_lines(std::io::Cursor::new(input)).collect::<Result<Vec<_>>>()?;
+    assert_eq!(lines, ["a\rb", "last\r"]);
+
+    // a line spanning several chunks
+    let long = "x".repeat(60000);
+    let mut input = stored_member(0, format!("short\n{long}").as_bytes());
+    input.extend(stored_member(0, long.as_bytes()));
+    input.extend(stored_member(0, format!("{long}\nend").as_bytes()));
+    let lines = gzip_lines(std::io::Cursor::new(input)).collect::<Result<Vec<_>>>()?;
+    assert_eq!(
+        lines,
+        ["short".to_string(), long.repeat(3), "end".to_string()]
+    );
+
+    let mut input = stored_member(0, b"line\nbroken");
+    input[13] ^= 1;
+    let mut lines = gzip_lines(std::io::Cursor::new(input));
+    assert!(lines.next().unwrap().is_err());
+    assert!(lines.next().is_none());
+
+    Ok(())
+}
+
+#[test]
+fn chunks() -> Result<()> {
+    let input = include_bytes!("../data/ok/06-war-and-peace.txt.gz");
+    let mut expected = Vec::new();
+    decompress(input.as_slice(), &mut expected)?;
+
+    let chunks = decompress_chunks(input.as_slice()).collect::<Result<Vec<_>>>()?;
+    let (last, full) = chunks.split_last().unwrap();
+    assert!(full.iter().all(|chunk| chunk.len() == OUTPUT_CHUNK_SIZE));
+    assert!(!last.is_empty() && last.len() <= OUTPUT_CHUNK_SIZE);
+    assert_eq!(chunks.concat(), expected);
+
+    let input = include_bytes!("../data/ok/12-empty.gz");
+    assert_eq!(decompress_chunks(input.as_slice()).count(), 0);
+
+    let mut input = stored_member(0, b"data");
+    input[13] ^= 1;
+    let mut chunks = decompress_chunks(std::io::Cursor::new(input));
+    assert!(chunks.next().unwrap().is_err());
+    assert!(chunks.next().is_none());
+
+    Ok(())
+}
+
+#[test]
+fn chunks_before_truncation() -> Result<()> {
+    let mut input = stored_member(0, b"line one\nline two\npart");
+    let truncated = stored_member(0, b"ial");
+    input.extend(&truncated[..truncated.len() - 8]);
+
+    let mut chunks = decompress_chunks(std::io::Cursor::new(input.clone()));
+    assert_eq!(chunks.next().unwrap()?, b"line one\nline two\npartial");
+    assert!(chunks.next().unwrap().is_err());
+    assert!(chunks.next().is_none());
+
+    // an incomplete line is dropped, but the ones before it are not
+    let mut lines = gzip_lines(std::io::Cursor::new(input));
+    assert_eq!(lines.next().unwrap()?, "line one");
+    assert_eq!(lines.next().unwrap()?, "line two");
+    assert!(lines.next().unwrap().is_err());
+    assert!(lines.next().is_none());
+
+    Ok(())
+}
+
+// yields its data, then panics instead of reporting the end of input, like a buggy source
+struct PanickingReader(&'static [u8]);
+
+impl Read for PanickingReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let len = self.fill_buf()?.read(buf)?;
+        self.consume(len);
+        Ok(len)
+    }
+}
+
+impl BufRead for PanickingReader {
+    fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+        assert!(!self.0.is_empty(), "reader panicked");
+        Ok(self.0)
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.0 = &self.0[amt..];
+    }
+}
+
+#[test]
+fn decoding_thread_panic() {
+    let input: &'static [u8] = stored_member(0, b"line\n").leak();
+
+    let mut chunks = decompress_chunks(PanickingReader(input));
+    let error = chunks.next().unwrap().unwrap_err();
+    assert!(error.to_string().contains("panicked"), "{error}");
+    assert!(chunks.next().is_none());
+
+    let mut lines = gzip_lines(PanickingReader(input));
+    assert!(lines
+        .next()
+        .unwrap()
+        .unwrap_err()
+        .to_string()
+        .contains("panicked"));
+    assert!(lines.next().is_none());
+}
+
+#[test]
+fn whole_output_crc32() -> Result<()> {
+    let crc = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+    assert_eq!(
+        decompressed_crc32(include_bytes!("../data/ok/11-fixed-tree.gz").as_slice())?,
+        crc.checksum(b"abcabcabcabc")
+    );
+
+    // spans all members, unlike the footers
+    let mut input = stored_member(0, b"first ");
+    input.extend(stored_member(0, b"second"));
+    assert_eq!(
+        decompressed_crc32(input.as_slice())?,
+        crc.checksum(b"first second")
+    );
+
+    input[20] ^= 1;
+    assert!(decompressed_crc32(input.as_slice()).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn reusable_decompressor() -> Result<()> {
+    let mut decompressor = Decompressor::default();
+
+    for data in [&b"first file"[..], b"second", b""] {
+        let mut output = Vec::new();
+        let stats = decompressor.decompress(stored_member(0, data).as_slice(), &mut output)?;
+        assert_eq!(output, data);
+        assert_eq!(stats.members.len(), 1);
+    }
+
+    let mut corrupted = stored_member(0, b"data");
+    corrupted[13] ^= 1;
+    assert!(decompressor
+        .decompress(corrupted.as_slice(), std::io::sink())
+        .is_err());
+
+    let input = include_bytes!("../data/ok/11-fixed-tree.gz");
+    let mut output = Vec::new();
+    decompressor.decompress(input.as_slice(), &mut output)?;
+    assert_eq!(output, b"abcabcabcabc");
+
+    // the options of the decompressor apply to every call
+    let mut decompressor = Decompressor::new(DecompressOptions {
+        skip_corrupt_members: true,
+        ..Default::default()
+    });
+    let mut input = stored_member(0, b"first");
+    input.extend(&corrupted);
+    input.extend(stored_member(0, b"last"));
+    for _ in 0..2 {
+        let mut output = Vec::new();
+        let stats = decompressor.decompress(input.as_slice(), &mut output)?;
+        assert_eq!(output, b"firstlast");
+        assert_eq!(stats.members.len(), 2);
+        assert_eq!(stats.skipped_members.len(), 1);
+        assert_eq!(stats.skipped_members[0].offset, 28);
+    }
+
+    Ok(())
+}
+
+#[derive(Default)]
+struct FlushLog {
+    data: Vec<u8>,
+    // data length at every flush
+    flushes: Vec<usize>,
+}
+
+impl Write for FlushLog {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.data.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.flushes.push(self.data.len());
+        Ok(())
+    }
+}
+
+#[test]
+fn flush_each_member() -> Result<()> {
+    let mut input = stored_member(0, b"abcd");
+    input.extend(include_bytes!("../data/ok/11-fixed-tree.gz"));
+
+    let options = DecompressOptions {
+        flush_each_member: true,
+        ..Default::default()
+    };
+    let mut output = FlushLog::default();
+    decompress_with_options(input.as_slice(), &mut output, &options)?;
+    assert_eq!(output.flushes, [4, 16, 16]);
+
+    // the output is flushed at the end anyway
+    let mut output = FlushLog::default();
+    decompress(input.as_slice(), &mut output)?;
+    assert_eq!(output.flushes, [16]);
+
+    Ok(())
+}
+
+#[derive(Default)]
+struct WriteCount {
+    data: Vec<u8>,
+    writes: usize,
+}
+
+impl Write for WriteCount {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.writes += 1;
+        self.data.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn output_buffer_size() -> Result<()> {
+    let input = include_bytes!("../data/ok/00-Cargo.toml.gz");
+
+    let mut unbuffered = WriteCount::default();
+    decompress(input.as_slice(), &mut unbuffered)?;
+
+    let options = DecompressOptions {
+        output_buffer_size: Some(1 << 16),
+        ..Default::default()
+    };
+    let mut buffered = WriteCount::default();
+    decompress_with_options(input.as_slice(), &mut buffered, &options)?;
+    assert_eq!(buffered.data, unbuffered.data);
+    // the whole single block reaches the output at once, instead of a write per token
+    assert_eq!(buffered.writes, 1);
+    assert!(unbuffered.writes > 50);
+
+    // a block larger than the buffer is passed on in several writes
+    let options = DecompressOptions {
+        output_buffer_size: Some(64),
+        ..Default::default()
+    };
+    let mut buffered = WriteCount::default();
+    decompress_with_options(input.as_slice(), &mut buffered, &options)?;
+    assert_eq!(buffered.data, unbuffered.data);
+    assert!(buffered.writes > 1 && buffered.writes < unbuffered.writes);
+
+    // the same with a reusable decompressor, on every call
+    let mut decompressor = Decompressor::new(DecompressOptions {
+        output_buffer_size: Some(1 << 16),
+        ..Default::default()
+    });
+    for _ in 0..2 {
+        let mut buffered = WriteCount::default();
+        decompressor.decompress(input.as_slice(), &mut buffered)?;
+        assert_eq!(buffered.data, unbuffered.data);
+        assert_eq!(buffered.writes, 1);
+    }
+
+    Ok(())
+}
+
+// every block is flushed, so buffered data doesn't lag behind by more than a block
+#[test]
+fn output_buffer_flush() -> Result<()> {
+    let mut input = stored_member(0, b"abcd");
+    input.extend(include_bytes!("../data/ok/11-fixed-tree.gz"));
+
+    let options = DecompressOptions {
+        output_buffer_size: Some(1 << 16),
+        ..Default::default()
+    };
+    let mut output = FlushLog::default();
+    decompress_with_options(input.as_slice(), &mut output, &options)?;
+    assert_eq!(output.flushes, [4, 16, 16]);
+
+    Ok(())
+}
+
+struct FailingFlush;
+
+impl Write for FailingFlush {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Err(std::io::Error::other("disk full"))
+    }
+}
+
+#[test]
+fn failing_flush() {
+    let input = stored_member(0, b"abcd");
+
+    let error = decompress(input.as_slice(), FailingFlush).unwrap_err();
+    assert!(format!("{:#}", error).contains("disk full"));
+
+    // the output is flushed after an accepted truncated final member as well
+    let input = &input[..input.len() - 2];
+    for output_buffer_size in [None, Some(1 << 16)] {
+        let options = DecompressOptions {
+            allow_truncated_final_member: true,
+            output_buffer_size,
+            ..Default::default()
+        };
+        let error = decompress_with_options(input, FailingFlush, &options).unwrap_err();
+        assert!(format!("{:#}", error).contains("disk full"));
+    }
+}
+
+// a distance within the window but before the start of output isn't DistanceTooFar
+#[test]
+fn distance_before_output_start() {
+    let input = include_bytes!("../data/corrupted/09-cross-member-reference.gz");
+
+    let error = decompress(input.as_slice(), std::io::sink()).unwrap_err();
+    assert!(format!("{:#}", error).contains("Wrong Length/Distance"));
+    assert_eq!(error.downcast_ref::<DecompressError>(), None);
+}
+
+// length code 284 and distance code 29 with all extra bits set: 258 bytes from 32768 back
+#[test]
+fn longest_match() -> Result<()> {
+    let data = (0..32768).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+    let mut expected = data.clone();
+    expected.extend_from_slice(&data[..258]);
+
+    let mut input = vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 255, 0];
+    input.extend(32768u16.to_le_bytes());
+    input.extend((!32768u16).to_le_bytes());
+    input.extend(&data);
+    input.extend([27, 249, 247, 255, 3, 0]);
+    input.extend(
+        Crc::<u32>::new(&CRC_32_ISO_HDLC)
+            .checksum(&expected)
+            .to_le_bytes(),
+    );
+    input.extend((expected.len() as u32).to_le_bytes());
+
+    let mut output = Vec::new();
+    decompress(input.as_slice(), &mut output)?;
+    assert_eq!(output, expected);
+
+    Ok(())
+}
+
+// an "uncompressed" member of stored blocks only, as written for already compressed payloads
+#[test]
+fn stored_only_member() -> Result<()> {
+    let data = (0..100_000u32)
+        .map(|i| (i.wrapping_mul(2_654_435_761) >> 24) as u8)
+        .collect::<Vec<_>>();
+
+    let mut input = vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 255];
+    let mut blocks = data.chunks(u16::MAX as usize).peekable();
+    while let Some(block) = blocks.next() {
+        input.push(blocks.peek().is_none() as u8);
+        input.extend((block.len() as u16).to_le_bytes());
+        input.extend((!(block.len() as u16)).to_le_bytes());
+        input.extend(block);
+    }
+    let crc32 = Crc::<u32>::new(&CRC_32_ISO_HDLC).checksum(&data);
+    input.extend(crc32.to_le_bytes());
+    input.extend((data.len() as u32).to_le_bytes());
+
+    let mut output = Vec::new();
+    let stats = decompress_with_options(input.as_slice(), &mut output, &Default::default())?;
+    assert_eq!(output, data);
+
+    let member = &stats.members[0];
+    assert_eq!(member.computed_crc32, Some(crc32));
+    assert_eq!(member.footer.data_size, data.len() as u32);
+    assert_eq!(stats.block_types.stored.blocks, 2);
+    assert_eq!(
+        stats.block_types.fixed.blocks + stats.block_types.dynamic.blocks,
+        0
+    );
+
+    // ISIZE is checked as for any other member
+    let len = input.len();
+    input[len - 4] ^= 1;
+    let error = decompress(input.as_slice(), std::io::sink()).unwrap_err();
+    assert!(matches!(
+        error.downcast_ref(),
+        Some(DecompressError::LengthMismatch { .. })
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn lenient_length() -> Result<()> {
+    let mut input = stored_member(0, b"data");
+    let len = input.len();
+    input[len - 4] = 5;
+
+    let error = decompress(input.as_slice(), std::io::sink()).unwrap_err();
+    assert!(matches!(
+        error.downcast_ref(),
+        Some(DecompressError::LengthMismatch { .. })
+    ));
+
+    let options = DecompressOptions {
+        lenient_length: true,
+        ..Default::default()
+    };
+    let mut output = Vec::new();
+    let stats = decompress_with_options(input.as_slice(), &mut output, &options)?;
+    assert_eq!(output, b"data");
+    assert_eq!(stats.members[0].footer.data_size, 5);
+
+    // without a matching CRC32 the data isn't confirmed
+    input[len - 8] ^= 1;
+    let error = decompress_with_options(input.as_slice(), std::io::sink(), &options).unwrap_err();
+    assert!(matches!(
+        error.downcast_ref(),
+        Some(DecompressError::LengthMismatch { .. })
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn truncated_stored_block() {
+    let mut input = stored_member(0, &[42; 20000]);
+    input.truncate(10 + 5 + 12345);
+
+    let error = decompress(input.as_slice(), std::io::sink()).unwrap_err();
+    let message = format!("{:#}", error);
+    assert!(message.contains("12345 of 20000 bytes read"), "{message}");
+    assert!(message.contains("input ends at byte 12360"), "{message}");
+}
+
+// hands out at most one byte per read call
+struct OneByteReader<'a>(&'a [u8]);
+
+impl std::io::Read for OneByteReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let len = buf.len().min(self.0.len()).min(1);
+        buf[..len].copy_from_slice(&self.0[..len]);
+        self.0 = &self.0[len..];
+        Ok(len)
+    }
+}
+
+#[test]
+fn unbuffered_reader() -> Result<()> {
+    let input = include_bytes!("../data/ok/00-Cargo.toml.gz");
+
+    let mut expected = Vec::new();
+    decompress(input.as_slice(), &mut expected)?;
+
+    let mut output = Vec::new();
+    decompress_reader(OneByteReader(input), &mut output)?;
+    assert_eq!(output, expected);
+
+    Ok(())
+}
+
+#[test]
+fn fragmented_input() -> Result<()> {
+    // every optional header field, stored and fixed tree blocks
+    let header = MemberHeader::builder()
+        .name("file.txt")
+        .comment("comment")
+        .extra(vec![b'A', b'B', 2, 0, 1, 2])
+        .has_crc(true)
+        .build();
+    let mut input = stored_member(0, b"stored");
+    input.splice(..10, header.to_bytes());
+    input.extend(include_bytes!("../data/ok/11-fixed-tree.gz"));
+    // dynamic blocks, spanning several buffer refills of the usual readers
+    input.extend(include_bytes!("../data/ok/01-page.gz"));
+
+    let mut expected = Vec::new();
+    decompress(input.as_slice(), &mut expected)?;
+
+    // a buffer of one byte makes every read and fill_buf return a single byte
+    let mut output = Vec::new();
+    let fragmented = std::io::BufReader::with_capacity(1, OneByteReader(&input));
+    decompress(fragmented, &mut output)?;
+    assert_eq!(output, expected);
+
+    Ok(())
+}
+
+#[test]
+fn skip_corrupt_members() -> Result<()> {
+    let mut input = stored_member(0, b"first");
+    let mut bad_crc = stored_member(0, b"bad");
+    let len = bad_crc.len();
+    bad_crc[len - 8] ^= 1;
+    input.extend(bad_crc);
+    let mut bad_block_type = stored_member(0, b"lost");
+    bad_block_type[10] = 0b111;
+    input.extend(bad_block_type);
+    input.extend(stored_member(0, b"last"));
+
+    assert!(decompress(input.as_slice(), std::io::sink()).is_err());
+
+    let options = DecompressOptions {
+        skip_corrupt_members: true,
+        ..Default::default()
+    };
+    // a tiny buffer splits the magic of the members between reads
+    for capacity in [1, 2, 3, 64] {
+        let reader = std::io::BufReader::with_capacity(capacity, input.as_slice());
+        let mut output = Vec::new();
+        let stats = decompress_with_options(reader, &mut output, &options)?;
+
+        // the data of the member with a bad CRC32 is written before its footer is checked
+        assert_eq!(output, b"firstbadlast");
+        assert_eq!(stats.members.len(), 2);
+        assert_eq!(stats.end_offset, input.len() as u64);
+
+        let skipped: Vec<_> = stats
+            .skipped_members
+            .iter()
+            .map(|member| (member.offset, member.resume_offset))
+            .collect();
+        assert_eq!(skipped, [(28, 54), (54, 81)]);
+        let message = format!("{:#}", stats.skipped_members[1].error);
+        assert!(message.contains("unsupported block type"), "{message}");
+    }
+
+    Ok(())
+}
+
+#[test]
+fn skip_corrupt_members_to_end() -> Result<()> {
+    let mut input = stored_member(0, b"data");
+    input.extend(b"\x1f\x1f\x8b junk");
+
+    let options = DecompressOptions {
+        skip_corrupt_members: true,
+        ..Default::default()
+    };
+    let mut output = Vec::new();
+    let stats = decompress_with_options(input.as_slice(), &mut output, &options)?;
+    assert_eq!(output, b"data");
+    assert_eq!(stats.members.len(), 1);
+    assert_eq!(stats.end_offset, 27);
+
+    // junk after the member, then a false magic inside it, both run to the end of input
+    let skipped: Vec<_> = stats
+        .skipped_members
+        .iter()
+        .map(|member| (member.offset, member.resume_offset))
+        .collect();
+    assert_eq!(skipped, [(27, 28), (28, input.len() as u64)]);
+
+    Ok(())
+}
+
+#[test]
+fn append_to_vec() -> Result<()> {
+    let mut output = Vec::with_capacity(64);
+    output.extend(b"prefix ");
+
+    assert_eq!(
+        decompress_append(stored_member(0, b"abc").as_slice(), &mut output)?,
+        3
+    );
+    assert_eq!(
+        decompress_append(stored_member(0, b"de").as_slice(), &mut output)?,
+        2
+    );
+    assert_eq!(output, b"prefix abcde");
+
+    // a match at the very start can't reach the bytes already in the buffer
+    let mut input = vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 255, 3, 2, 0];
+    input.extend([0; 8]);
+    let error = decompress_append(input.as_slice(), &mut output).unwrap_err();
+    let message = format!("{:#}", error);
+    assert!(message.contains("dist=1"), "{message}");
+    assert_eq!(output, b"prefix abcde");
+
+    Ok(())
+}
+
+#[test]
+fn first_block_type() -> Result<()> {
+    let input = stored_member(0, b"data");
+    assert_eq!(
+        peek_first_block_type(input.as_slice())?,
+        CompressionType::Uncompressed
+    );
+
+    let input = include_bytes!("../data/ok/11-fixed-tree.gz");
+    assert_eq!(
+        peek_first_block_type(input.as_slice())?,
+        CompressionType::FixedTree
+    );
+
+    let input = include_bytes!("../data/ok/01-page.gz");
+    assert_eq!(
+        peek_first_block_type(input.as_slice())?,
+        CompressionType::DynamicTree
+    );
+
+    // only the 3 header bits are read, so the data after them doesn't matter
+    let input = [0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 255, 0b111];
+    assert_eq!(
+        peek_first_block_type(input.as_slice())?,
+        CompressionType::Reserved
+    );
+
+    assert!(peek_first_block_type(&[0x1f, 0x8b, 8, 0][..]).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn whole_output_crc32_in_stats() -> Result<()> {
+    let input = include_bytes!("../data/ok/09-concat.gz");
+    let stats = decompress_with_options(input.as_slice(), std::io::sink(), &Default::default())?;
+    assert_eq!(stats.output_crc32, None);
+
+    let options = DecompressOptions {
+        whole_output_crc32: true,
+        ..Default::default()
+    };
+    let stats = decompress_with_options(input.as_slice(), std::io::sink(), &options)?;
+    assert!(stats.members.len() > 1);
+    assert_eq!(
+        stats.output_crc32,
+        Some(decompressed_crc32(input.as_slice())?)
+    );
+    assert_ne!(stats.output_crc32, stats.members[0].computed_crc32);
+
+    // of the output as written, unlike the per-member ones
+    let options = DecompressOptions {
+        whole_output_crc32: true,
+        text_mode: TextMode::ConvertNewlines(LineEnding::CrLf),
+        ..Default::default()
+    };
+    let mut output = Vec::new();
+    let input = stored_member(FTEXT, b"a\nb");
+    let stats = decompress_with_options(input.as_slice(), &mut output, &options)?;
+    assert_eq!(output, b"a\r\nb");
+    let crc = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+    assert_eq!(stats.output_crc32, Some(crc.checksum(&output)));
+    assert_ne!(stats.output_crc32, stats.members[0].computed_crc32);
+    let input = include_bytes!("../data/ok/09-concat.gz");
+
+    let options = DecompressOptions {
+        whole_output_crc32: true,
+        skip_corrupt_members: true,
+        ..Default::default()
+    };
+    let mut output = Vec::new();
+    let error = decompress_with_options(input.as_slice(), &mut output, &options).unwrap_err();
+    assert!(error.to_string().contains("can't be combined"), "{error}");
+    assert!(output.is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn single_member() -> Result<()> {
+    let mut output = Vec::new();
+    let header = ensure_single_member(stored_member(FTEXT, b"one").as_slice(), &mut output)?;
+    assert!(header.is_text);
+    assert_eq!(output, b"one");
+
+    let mut input = stored_member(0, b"one");
+    input.extend(stored_member(0, b"two"));
+    let error = ensure_single_member(input.as_slice(), std::io::sink()).unwrap_err();
+    assert_eq!(
+        error.downcast_ref(),
+        Some(&DecompressError::TooManyMembers { limit: 1 })
+    );
+
+    // trailing data isn't taken for another member
+    let mut input = stored_member(0, b"one");
+    input.extend(b"junk");
+    let error = ensure_single_member(input.as_slice(), std::io::sink()).unwrap_err();
+    assert!(matches!(
+        error.downcast_ref(),
+        Some(DecompressError::TrailingNonGzipData { offset: 26, .. })
+    ));
+
+    assert!(ensure_single_member(&[][..], std::io::sink()).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn length_without_distance_codes() {
+    // dynamic block with codes for 'a', end of block and length 3, but no distance codes,
+    // followed by 'a' and a length
+    let mut input = vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 255];
+    input.extend([13, 192, 1, 9, 0, 0, 0, 128, 160, 173, 254, 63, 81, 56]);
+    input.extend([0; 8]);
+
+    let mut output = Vec::new();
+    let error = decompress(input.as_slice(), &mut output).unwrap_err();
+    assert_eq!(error.downcast_ref(), Some(&DecompressError::EmptyCodeTable));
+    assert!(format!("{:#}", error).contains("without distance codes"));
+    assert_eq!(output, b"a");
+}
@@ -41,8 +41,15 @@ fn errors() {
         include_bytes!("../data/corrupted/07-invalid-cm.gz"),
         "unsupported compression method",
     );
+    let mut reserved_cm = include_bytes!("../data/corrupted/07-invalid-cm.gz").to_vec();
+    reserved_cm[2] = 7;
+    check_decompression_error(&reserved_cm, "reserved compression method");
     check_decompression_error(
         include_bytes!("../data/corrupted/08-bad-nlen.gz"),
         "nlen check failed",
     );
+    check_decompression_error(
+        include_bytes!("../data/corrupted/09-cross-member-reference.gz"),
+        "Wrong Length/Distance",
+    );
 }
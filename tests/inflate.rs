@@ -0,0 +1,134 @@
+use std::io::{Read, Write};
+
+use anyhow::Result;
+use flate2::{write::DeflateEncoder, Compression};
+
+use ripgzip::{CompressionType, GrowableInput, InflateState};
+
+#[test]
+fn custom_framing() -> Result<()> {
+    let data = b"raw deflate inside a custom container ".repeat(50);
+    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::best());
+    encoder.write_all(&data)?;
+    let mut input = encoder.finish()?;
+    input.extend(b"trailer");
+
+    let mut state = InflateState::new(input.as_slice(), Vec::new());
+    let mut headers = Vec::new();
+    while let Some(header) = state.next_block()? {
+        headers.push(header);
+    }
+    assert!(state.is_finished());
+    assert_eq!(state.output_size(), data.len() as u64);
+    assert!(state.next_block()?.is_none());
+
+    assert!(headers.last().unwrap().is_final);
+    assert_ne!(headers[0].compression_type, CompressionType::Reserved);
+
+    let (mut rest, output) = state.into_inner()?;
+    assert_eq!(output, data);
+    let mut trailer = Vec::new();
+    rest.read_to_end(&mut trailer)?;
+    assert_eq!(trailer, b"trailer");
+
+    Ok(())
+}
+
+#[test]
+fn wait_for_input() -> Result<()> {
+    let mut input = GrowableInput::new();
+    // a non-final stored block with "ab"
+    input.push(&[0, 2, 0, 0xfd, 0xff, b'a', b'b']);
+    let mut state = InflateState::new(input, Vec::new());
+
+    assert!(!state.next_block()?.unwrap().is_final);
+    assert!(is_would_block(&state.next_block().unwrap_err()));
+
+    // nothing was lost while waiting
+    let input = state.input_mut().unwrap();
+    input.push(&[1, 2, 0, 0xfd, 0xff, b'c', b'd']);
+    input.close();
+    assert!(state.next_block()?.unwrap().is_final);
+
+    let (_, output) = state.into_inner()?;
+    assert_eq!(output, b"abcd");
+
+    Ok(())
+}
+
+fn is_would_block(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| {
+        cause
+            .downcast_ref::<std::io::Error>()
+            .is_some_and(|error| error.kind() == std::io::ErrorKind::WouldBlock)
+    })
+}
+
+#[test]
+fn input_ends_mid_block() -> Result<()> {
+    let mut input = GrowableInput::new();
+    input.push(&[1, 2, 0, 0xfd, 0xff, b'c']);
+    let mut state = InflateState::new(input, Vec::new());
+
+    assert!(is_would_block(&state.next_block().unwrap_err()));
+    // stored content is copied in whole chunks
+    assert_eq!(state.output_size(), 0);
+
+    let input = state.input_mut().unwrap();
+    input.push(b"d");
+    input.close();
+    assert!(state.next_block()?.unwrap().is_final);
+
+    let (_, output) = state.into_inner()?;
+    assert_eq!(output, b"cd");
+
+    Ok(())
+}
+
+#[test]
+fn input_pushed_byte_by_byte() -> Result<()> {
+    let data = b"split in the middle of every token and header ".repeat(200);
+    let mut compressed = Vec::new();
+    for level in [
+        Compression::fast(),
+        Compression::best(),
+        Compression::none(),
+    ] {
+        let mut encoder = DeflateEncoder::new(Vec::new(), level);
+        encoder.write_all(&data)?;
+        compressed.push(encoder.finish()?);
+    }
+
+    for compressed in compressed {
+        let mut state = InflateState::new(GrowableInput::new(), Vec::new());
+        let mut types = Vec::new();
+        for &byte in &compressed {
+            state.input_mut().unwrap().push(&[byte]);
+            loop {
+                match state.next_block() {
+                    Ok(Some(header)) => types.push(header.compression_type),
+                    Ok(None) => break,
+                    Err(error) if is_would_block(&error) => break,
+                    Err(error) => return Err(error),
+                }
+            }
+        }
+
+        assert!(state.is_finished());
+        assert!(!types.is_empty());
+        let (_, output) = state.into_inner()?;
+        assert_eq!(output, data);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn unusable_after_error() {
+    // BTYPE = 11 is reserved
+    let mut state = InflateState::new([0b111u8, 0].as_slice(), std::io::sink());
+    assert!(state.next_block().is_err());
+    assert!(state.next_block().is_err());
+    assert!(!state.is_finished());
+    assert!(state.into_inner().is_err());
+}
@@ -0,0 +1,61 @@
+#![cfg(feature = "recompress")]
+
+use std::io::Write;
+
+use anyhow::Result;
+use crc::{Crc, CRC_32_ISO_HDLC};
+use flate2::{write::GzEncoder, Compression};
+
+use ripgzip::recompress_matches;
+
+fn compress(data: &[u8], level: u32) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::new(level));
+    encoder.write_all(data).unwrap();
+    encoder.finish().unwrap()
+}
+
+#[test]
+fn flate2_output_matches() -> Result<()> {
+    let text = include_bytes!("../data/ok/00-Cargo.toml.gz");
+    let mut data = Vec::new();
+    ripgzip::decompress(text.as_slice(), &mut data)?;
+
+    for level in [0, 1, 6, 9] {
+        assert!(
+            recompress_matches(compress(&data, level).as_slice())?,
+            "{level}"
+        );
+    }
+
+    // every member is checked on its own
+    let mut input = compress(b"first member", 9);
+    input.extend(compress(&data, 1));
+    assert!(recompress_matches(input.as_slice())?);
+
+    Ok(())
+}
+
+#[test]
+fn other_encoding_differs() -> Result<()> {
+    // the same data in two stored blocks, where flate2 uses a single one at level 0
+    let data = b"two stored blocks";
+    let mut input = vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 255];
+    for (is_final, part) in [(0, &data[..4]), (1, &data[4..])] {
+        input.push(is_final);
+        input.extend((part.len() as u16).to_le_bytes());
+        input.extend((!(part.len() as u16)).to_le_bytes());
+        input.extend(part);
+    }
+    let crc = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+    input.extend(crc.checksum(data).to_le_bytes());
+    input.extend((data.len() as u32).to_le_bytes());
+
+    let mut output = Vec::new();
+    ripgzip::decompress(input.as_slice(), &mut output)?;
+    assert_eq!(output, data);
+    assert!(!recompress_matches(input.as_slice())?);
+
+    assert!(recompress_matches(&input[..input.len() - 1]).is_err());
+
+    Ok(())
+}
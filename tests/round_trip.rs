@@ -0,0 +1,43 @@
+use std::io::Write;
+
+use flate2::{write::GzEncoder, Compression};
+use proptest::{collection::vec, prelude::*};
+
+// the crate has no encoder yet, so flate2 produces the gzip data;
+// level 0 gives stored blocks, small inputs get fixed blocks, larger ones dynamic
+fn compress(data: &[u8], level: u32) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::new(level));
+    encoder.write_all(data).unwrap();
+    encoder.finish().unwrap()
+}
+
+fn decompress(input: &[u8]) -> Vec<u8> {
+    let mut output = Vec::new();
+    ripgzip::decompress(input, &mut output).unwrap();
+    output
+}
+
+proptest! {
+    #[test]
+    fn arbitrary_bytes(data in vec(any::<u8>(), 0..10000), level in 0u32..=9) {
+        prop_assert_eq!(decompress(&compress(&data, level)), data);
+    }
+
+    // a small alphabet gives lots of back-references, including overlapping ones
+    #[test]
+    fn repetitive_bytes(data in vec(0u8..4, 0..20000), level in 0u32..=9) {
+        prop_assert_eq!(decompress(&compress(&data, level)), data);
+    }
+
+    #[test]
+    fn multiple_members(parts in vec((vec(0u8..8, 0..2000), 0u32..=9), 1..5)) {
+        let mut input = Vec::new();
+        let mut expected = Vec::new();
+        for (data, level) in &parts {
+            input.extend(compress(data, *level));
+            expected.extend(data);
+        }
+
+        prop_assert_eq!(decompress(&input), expected);
+    }
+}
@@ -0,0 +1,91 @@
+use std::io::Write;
+
+use anyhow::Result;
+use flate2::{write::ZlibEncoder, Compression};
+
+use ripgzip::{decompress_zlib, DecompressError, ZlibDecoder};
+
+// "the lazy dog jumps over the quick brown fox" compressed by python's zlib with DICTIONARY
+const DICTIONARY: &[u8] = b"the quick brown fox jumps over the lazy dog";
+const WITH_DICTIONARY: [u8; 18] = [
+    120, 249, 97, 60, 15, 250, 67, 102, 163, 171, 65, 51, 2, 0, 93, 102, 15, 250,
+];
+
+#[test]
+fn zlib() -> Result<()> {
+    let data = b"zlib zlib zlib data".repeat(100);
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::best());
+    encoder.write_all(&data)?;
+    let input = encoder.finish()?;
+
+    let mut output = Vec::new();
+    assert_eq!(
+        decompress_zlib(input.as_slice(), &mut output)?,
+        data.len() as u64
+    );
+    assert_eq!(output, data);
+
+    let mut corrupted = input.clone();
+    *corrupted.last_mut().unwrap() ^= 1;
+    assert!(decompress_zlib(corrupted.as_slice(), std::io::sink()).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn distance_too_far() -> Result<()> {
+    let mut data: Vec<u8> = (0..=255).collect();
+    data.extend([b'a'; 50]);
+    data.extend(0..=255);
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::best());
+    encoder.write_all(&data)?;
+    let mut input = encoder.finish()?;
+
+    // declare a 256-byte window (CINFO = 0), fixing up the header check bits
+    input[0] &= 0x0f;
+    input[1] &= !0x1f;
+    input[1] += (31 - (u16::from(input[0]) << 8 | u16::from(input[1])) % 31) as u8 % 31;
+
+    let error = decompress_zlib(input.as_slice(), std::io::sink()).unwrap_err();
+    assert_eq!(
+        error.downcast_ref::<DecompressError>(),
+        Some(&DecompressError::DistanceTooFar {
+            dist: 306,
+            window: 256
+        })
+    );
+
+    Ok(())
+}
+
+#[test]
+fn preset_dictionary() -> Result<()> {
+    let mut output = Vec::new();
+    ZlibDecoder::new()
+        .with_dictionary(DICTIONARY)
+        .decompress(WITH_DICTIONARY.as_slice(), &mut output)?;
+    assert_eq!(output, b"the lazy dog jumps over the quick brown fox");
+
+    let error = decompress_zlib(WITH_DICTIONARY.as_slice(), std::io::sink()).unwrap_err();
+    assert_eq!(
+        error.downcast_ref(),
+        Some(&DecompressError::DictionaryMismatch {
+            expected: 0x613c0ffa,
+            actual: None
+        })
+    );
+
+    let error = ZlibDecoder::new()
+        .with_dictionary(b"another dictionary".as_slice())
+        .decompress(WITH_DICTIONARY.as_slice(), std::io::sink())
+        .unwrap_err();
+    assert!(matches!(
+        error.downcast_ref(),
+        Some(DecompressError::DictionaryMismatch {
+            expected: 0x613c0ffa,
+            actual: Some(_)
+        })
+    ));
+
+    Ok(())
+}